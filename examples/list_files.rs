@@ -1,4 +1,3 @@
-use std::fs::File;
 use std::path::PathBuf;
 
 use anyhow::{Context, Error};
@@ -20,24 +19,17 @@ fn main() -> Result<(), Error> {
 
     println!("extracting ISO metadata");
 
-    let source_iso_file = File::open(&args.source_iso).context("error opening source ISO file")?;
+    let source_iso_file =
+        iso::ImageReader::open(&args.source_iso).context("error opening source ISO file")?;
 
     let source_iso = iso::IsoReader::read(source_iso_file).context("error reading source ISO")?;
 
     println!("{:?}", source_iso.volume_descriptor);
     println!("max used size: {}", source_iso.get_max_used_prefix_size());
 
-    print_dir(String::new(), &source_iso.directory_table);
+    for (path, entry) in source_iso.directory_table.walk().files_only() {
+        println!("{:9} {}", entry.size, path);
+    }
 
     Ok(())
 }
-
-fn print_dir(path: String, dir: &iso::DirectoryTable) {
-    for entry in dir.entries.iter() {
-        if let Some(subdir) = &entry.subdirectory {
-            print_dir(path.clone() + "/" + &entry.name, subdir);
-        } else {
-            println!("{:9} {}/{}", entry.size, path, entry.name);
-        }
-    }
-}
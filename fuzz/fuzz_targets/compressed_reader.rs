@@ -0,0 +1,21 @@
+#![no_main]
+
+use std::io::{Cursor, Read, Seek, SeekFrom, sink};
+
+use libfuzzer_sys::fuzz_target;
+
+use iso2god::iso::CompressedReader;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut reader) = CompressedReader::new(Cursor::new(data)) else {
+        return;
+    };
+
+    // images can claim to be much larger than the input, so only the first
+    // and the last blocks are read
+    let _ = std::io::copy(&mut (&mut reader).take(0x0010_0000), &mut sink());
+
+    if reader.seek(SeekFrom::End(-1)).is_ok() {
+        let _ = reader.read(&mut [0u8; 1]);
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+
+use iso2god::god::ConHeader;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = ConHeader::read(Cursor::new(data));
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use std::io::{Cursor, sink};
+
+use libfuzzer_sys::fuzz_target;
+
+use iso2god::executable::TitleInfo;
+use iso2god::iso::{ImageHealth, IsoReader, XgdImageInfo};
+
+// the volume descriptor is 64 KiB in, so this needs -max_len of at least 0x11000
+fuzz_target!(|data: &[u8]| {
+    let Ok(iso) = IsoReader::read(Cursor::new(data)) else {
+        return;
+    };
+
+    for (path, _) in iso.directory_table.walk() {
+        if let Ok(Some(mut file)) = iso.get_entry(&path) {
+            let _ = std::io::copy(&mut file, &mut sink());
+        }
+    }
+
+    let _ = ImageHealth::check(&iso);
+    let _ = XgdImageInfo::read(&iso);
+
+    if let Ok(title_info) = TitleInfo::from_image(&iso) {
+        let _ = title_info.game_title();
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use iso2god::executable::lzx;
+
+fuzz_target!(|data: &[u8]| {
+    // the window and output sizes come from the XEX header, so they are taken
+    // from the input as well
+    let [window_bits, size_high, size_low, input @ ..] = data else {
+        return;
+    };
+
+    let window_size = 1 << (15 + window_bits % 7);
+    let output_size = u16::from_be_bytes([*size_high, *size_low]) as usize * 4;
+
+    let _ = lzx::decompress(input, window_size, output_size);
+});
@@ -0,0 +1,19 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+
+use iso2god::executable::xbe::XbeHeader;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(header) = XbeHeader::read(Cursor::new(data)) else {
+        return;
+    };
+
+    for section in &header.fields.sections {
+        let _ = header.read_section(Cursor::new(data), &section.name);
+    }
+
+    let _ = header.read_logo_bitmap(Cursor::new(data));
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use iso2god::executable::xdbf::Xdbf;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(xdbf) = Xdbf::parse(data.to_vec()) else {
+        return;
+    };
+
+    let _ = xdbf.title_image();
+    let _ = xdbf.images().count();
+    let _ = xdbf.achievements();
+    let _ = xdbf.default_language();
+
+    for language in xdbf.languages() {
+        let _ = xdbf.string_table(language);
+        let _ = xdbf.title_name(language);
+    }
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+
+use iso2god::executable::xex::XexHeader;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(header) = XexHeader::read(Cursor::new(data)) else {
+        return;
+    };
+
+    // every resource read decodes the whole basefile again, so one will do
+    match header.fields.resource_info.first() {
+        Some(resource) => {
+            let _ = header.read_resource(Cursor::new(data), &resource.name);
+        }
+        None => {
+            let _ = header.read_basefile(Cursor::new(data));
+        }
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use iso2god::executable::xpr;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(bitmap) = xpr::decode(data) {
+        let _ = bitmap.to_icon_png();
+    }
+});
@@ -28,6 +28,8 @@ async function loadIsoFiles() {
 document.getElementById('source-iso-path').addEventListener('change', async (event) => {
     const selectedOptions = Array.from(event.target.selectedOptions);
     const gameTitleField = document.getElementById('game-title');
+    const isoReleaseField = document.getElementById('iso-release');
+    isoReleaseField.textContent = '';
 
     // Only auto-fill if single selection
     if (selectedOptions.length !== 1) {
@@ -53,6 +55,10 @@ document.getElementById('source-iso-path').addEventListener('change', async (eve
         if (info.success && info.game_title) {
             gameTitleField.value = info.game_title;
             gameTitleField.placeholder = info.game_title;
+
+            // media ID and creation time tell apart releases of the same title
+            isoReleaseField.textContent = `Title ID ${info.title_id}, media ID ${info.media_id}` +
+                (info.image_creation_time ? `, created ${info.image_creation_time}` : '');
         } else {
             gameTitleField.value = '';
             gameTitleField.placeholder = 'Could not detect game title';
@@ -549,8 +555,9 @@ async function convertBatch(filesToConvert, form, statusDiv, convertBtn, autoTra
                 formData.delete('source-iso-path');
             }
             
-            // Clear game title for batch (auto-detect each)
+            // Clear game title and icon for batch (auto-detect each)
             formData.set('game-title', '');
+            formData.delete('game-icon');
             
             const response = await fetch('/convert', {
                 method: 'POST',
@@ -1,15 +1,13 @@
 use std::collections::HashMap;
 use std::env;
 use std::fs::{self, File};
-use std::io::{Seek, SeekFrom, Write};
 use std::panic;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{Context, Error};
 
-use rocket::form::{Form, FromForm};
+use rocket::form::{Form, FromForm, FromFormField};
 use rocket::fs::{FileServer, TempFile};
 use rocket::response::stream::{Event, EventStream};
 use rocket::serde::json::Json;
@@ -18,12 +16,11 @@ use rocket::tokio::time::{Duration, interval};
 use rocket::{State, get, launch, post, routes};
 use rocket_dyn_templates::{Template, context};
 
+use iso2god::Iso2GodError;
 use iso2god::executable::TitleInfo;
+use iso2god::god;
 use iso2god::god::ContentType;
 use iso2god::iso;
-use iso2god::{game_list, god};
-
-use rayon::prelude::*;
 
 use suppaftp::FtpStream;
 use tempfile::tempdir;
@@ -97,12 +94,36 @@ struct ConversionForm<'f> {
     dest_dir: String,
     #[field(name = "game-title")]
     game_title: Option<String>,
+    #[field(name = "game-icon")]
+    game_icon: Option<TempFile<'f>>,
     #[field(name = "trim-mode")]
-    trim_mode: String,
+    trim_mode: TrimModeField,
     #[field(name = "num-threads")]
     num_threads: String,
     #[field(name = "dry-run")]
     dry_run: bool,
+    #[field(name = "force")]
+    force: bool,
+}
+
+#[derive(FromFormField, Clone, Copy)]
+enum TrimModeField {
+    #[field(value = "from-end")]
+    FromEnd,
+    #[field(value = "none")]
+    None,
+    #[field(value = "full-rebuild")]
+    FullRebuild,
+}
+
+impl From<TrimModeField> for god::TrimMode {
+    fn from(value: TrimModeField) -> Self {
+        match value {
+            TrimModeField::FromEnd => god::TrimMode::FromEnd,
+            TrimModeField::None => god::TrimMode::None,
+            TrimModeField::FullRebuild => god::TrimMode::FullRebuild,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -112,6 +133,8 @@ struct ConversionResponse {
     god_path: Option<String>,
     game_title: Option<String>,
     title_id: Option<String>,
+    /// Stable identifier of what went wrong, see `Iso2GodError::code`
+    error_code: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -119,7 +142,11 @@ struct IsoInfoResponse {
     success: bool,
     game_title: Option<String>,
     title_id: Option<String>,
+    media_id: Option<String>,
+    /// When the image was mastered, if it says so
+    image_creation_time: Option<String>,
     error: Option<String>,
+    error_code: Option<String>,
 }
 
 /// FTP connection configuration - used to reduce function arguments
@@ -216,6 +243,27 @@ struct FtpTestResponse {
     message: String,
 }
 
+#[derive(FromForm)]
+struct EditGodForm<'f> {
+    #[field(name = "god-path")]
+    god_path: String,
+    #[field(name = "game-title")]
+    game_title: Option<String>,
+    #[field(name = "game-icon")]
+    game_icon: Option<TempFile<'f>>,
+    #[field(name = "disc-number")]
+    disc_number: Option<u8>,
+    #[field(name = "disc-count")]
+    disc_count: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EditGodResponse {
+    success: bool,
+    message: String,
+    game_title: Option<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 struct FtpTransferResponse {
     success: bool,
@@ -290,9 +338,14 @@ fn list_converted_games() -> Json<Vec<ConvertedGame>> {
                 .and_then(|n| n.to_str())
                 .unwrap_or("Unknown");
 
+            let name = match read_game_title(&title_path) {
+                Some(game_title) => format!("{} ({})", game_title, title_id),
+                None => format!("Title ID: {}", title_id),
+            };
+
             games.push(ConvertedGame {
                 path: title_path.to_string_lossy().to_string(),
-                name: format!("Title ID: {}", title_id),
+                name,
             });
         }
     }
@@ -301,6 +354,28 @@ fn list_converted_games() -> Json<Vec<ConvertedGame>> {
     Json(games)
 }
 
+/// Find the CON header files in a title directory (TitleID/ContentType/MediaID)
+fn find_con_headers(title_path: &Path) -> Vec<PathBuf> {
+    WalkDir::new(title_path)
+        .min_depth(2)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Read the display title from the first CON header found in a title directory
+fn read_game_title(title_path: &Path) -> Option<String> {
+    find_con_headers(title_path)
+        .into_iter()
+        .filter_map(|path| File::open(path).ok())
+        .filter_map(|file| god::ConHeader::read(file).ok())
+        .map(|con_header| con_header.game_title)
+        .find(|game_title| !game_title.is_empty())
+}
+
 #[get("/list-isos")]
 fn list_isos() -> Json<Vec<IsoFile>> {
     let config = get_config();
@@ -317,25 +392,44 @@ fn list_isos() -> Json<Vec<IsoFile>> {
         .filter_map(|e| e.ok())
     {
         let path = entry.path();
-        if path.is_file()
-            && path
-                .extension()
-                .is_some_and(|ext| ext.eq_ignore_ascii_case("iso"))
-            && let Ok(metadata) = fs::metadata(path)
-        {
-            // Use relative path from input_dir for better display
-            let display_name = path
-                .strip_prefix(input_dir)
-                .unwrap_or(path)
-                .to_string_lossy()
-                .to_string();
-
-            iso_files.push(IsoFile {
-                path: path.to_string_lossy().to_string(),
-                name: display_name,
-                size: metadata.len(),
-            });
+        if !path.is_file() {
+            continue;
         }
+
+        // a split image is listed once, by its first chunk, with the total size
+        let size = match iso::split_image_parts(path) {
+            Some(parts) if parts[0] != path => continue,
+            Some(parts) => parts
+                .iter()
+                .filter_map(|part| fs::metadata(part).ok())
+                .map(|metadata| metadata.len())
+                .sum(),
+            None if path.extension().is_some_and(|ext| {
+                ["iso", "cci", "cso"]
+                    .iter()
+                    .any(|known| ext.eq_ignore_ascii_case(known))
+            }) =>
+            {
+                match fs::metadata(path) {
+                    Ok(metadata) => metadata.len(),
+                    Err(_) => continue,
+                }
+            }
+            None => continue,
+        };
+
+        // Use relative path from input_dir for better display
+        let display_name = path
+            .strip_prefix(input_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string();
+
+        iso_files.push(IsoFile {
+            path: path.to_string_lossy().to_string(),
+            name: display_name,
+            size,
+        });
     }
 
     iso_files.sort_by(|a, b| a.name.cmp(&b.name));
@@ -345,34 +439,45 @@ fn list_isos() -> Json<Vec<IsoFile>> {
 #[get("/iso-info?<path>")]
 fn get_iso_info(path: String) -> Json<IsoInfoResponse> {
     match get_iso_title_info(&path) {
-        Ok((title, id)) => Json(IsoInfoResponse {
-            success: true,
-            game_title: Some(title),
-            title_id: Some(id),
-            error: None,
-        }),
+        Ok(info) => Json(info),
         Err(e) => Json(IsoInfoResponse {
             success: false,
             game_title: None,
             title_id: None,
+            media_id: None,
+            image_creation_time: None,
             error: Some(e.to_string()),
+            error_code: Some(Iso2GodError::code_of(&e).to_owned()),
         }),
     }
 }
 
-fn get_iso_title_info(iso_path: &str) -> Result<(String, String), Error> {
-    let source_iso_file = File::open(iso_path).context("error opening source ISO file")?;
-    let mut source_iso_reader =
+fn get_iso_title_info(iso_path: &str) -> Result<IsoInfoResponse, Error> {
+    let source_iso_file =
+        iso::ImageReader::open(Path::new(iso_path)).context("error opening source ISO file")?;
+    let source_iso_reader =
         iso::IsoReader::read(source_iso_file).context("error reading source ISO")?;
     let title_info =
-        TitleInfo::from_image(&mut source_iso_reader).context("error reading image executable")?;
-    let exe_info = title_info.execution_info;
+        TitleInfo::from_image(&source_iso_reader).context("error reading image executable")?;
+    let exe_info = &title_info.execution_info;
 
     let title_id = format!("{:08X}", exe_info.title_id);
-    let game_name =
-        game_list::find_title_by_id(exe_info.title_id).unwrap_or("(unknown)".to_owned());
-
-    Ok((game_name, title_id))
+    let media_id = format!("{:08X}", exe_info.media_id);
+    let game_name = title_info.game_title().unwrap_or("(unknown)".to_owned());
+
+    let image_creation_time = source_iso_reader.volume_descriptor.image_creation_time;
+    let image_creation_time =
+        (!image_creation_time.is_zero()).then(|| image_creation_time.to_string());
+
+    Ok(IsoInfoResponse {
+        success: true,
+        game_title: Some(game_name),
+        title_id: Some(title_id),
+        media_id: Some(media_id),
+        image_creation_time,
+        error: None,
+        error_code: None,
+    })
 }
 
 #[post("/convert", data = "<form>")]
@@ -387,6 +492,7 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
                 god_path: None,
                 game_title: None,
                 title_id: None,
+                error_code: None,
             });
         }
         (PathBuf::from(iso_path), false)
@@ -401,6 +507,7 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
                     god_path: None,
                     game_title: None,
                     title_id: None,
+                    error_code: Some("io".to_owned()),
                 });
             }
         };
@@ -414,6 +521,7 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
                 god_path: None,
                 game_title: None,
                 title_id: None,
+                error_code: Some("io".to_owned()),
             });
         }
         (temp_path, true)
@@ -424,12 +532,42 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
             god_path: None,
             game_title: None,
             title_id: None,
+            error_code: None,
+        });
+    };
+
+    if let Some(Err(e)) = form.game_title.as_deref().map(god::check_game_title) {
+        return Json(ConversionResponse {
+            success: false,
+            message: format!("Invalid game title: {}", e),
+            god_path: None,
+            game_title: None,
+            title_id: None,
+            error_code: None,
         });
+    }
+
+    // Browsers send an empty file part when no icon was chosen
+    let game_icon = match form.game_icon.as_mut().filter(|icon| icon.len() > 0) {
+        Some(uploaded_icon) => match read_uploaded_file(uploaded_icon).await {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                return Json(ConversionResponse {
+                    success: false,
+                    message: format!("Failed to read uploaded icon: {}", e),
+                    god_path: None,
+                    game_title: None,
+                    title_id: None,
+                    error_code: None,
+                });
+            }
+        },
+        None => None,
     };
 
     let dest_dir_path = PathBuf::from(form.dest_dir.clone());
     let game_title = form.game_title.clone();
-    let trim_mode = form.trim_mode.clone();
+    let trim_mode: god::TrimMode = form.trim_mode.into();
 
     // Parse num_threads - handle "auto" or numeric value
     let num_threads = if form.num_threads == "auto" {
@@ -443,6 +581,13 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
 
     let dry_run = form.dry_run;
 
+    let options = god::ConvertOptions {
+        trim_mode,
+        game_title,
+        game_icon,
+        force: form.force,
+    };
+
     let source_iso_path_for_cleanup = source_iso_path.clone();
 
     let result = tokio::task::spawn_blocking(move || {
@@ -450,8 +595,7 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
             convert_iso(
                 source_iso_path,
                 dest_dir_path,
-                game_title,
-                trim_mode,
+                options,
                 num_threads,
                 dry_run,
             )
@@ -473,6 +617,7 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
             god_path: Some(god_path),
             game_title: Some(game_title),
             title_id: Some(title_id),
+            error_code: None,
         }),
         Ok(Ok(Err(e))) => Json(ConversionResponse {
             success: false,
@@ -480,6 +625,7 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
             god_path: None,
             game_title: None,
             title_id: None,
+            error_code: Some(Iso2GodError::code_of(&e).to_owned()),
         }),
         Ok(Err(_)) => Json(ConversionResponse {
             success: false,
@@ -487,6 +633,7 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
             god_path: None,
             game_title: None,
             title_id: None,
+            error_code: None,
         }),
         Err(e) => Json(ConversionResponse {
             success: false,
@@ -494,6 +641,7 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
             god_path: None,
             game_title: None,
             title_id: None,
+            error_code: None,
         }),
     }
 }
@@ -501,8 +649,7 @@ async fn convert(mut form: Form<ConversionForm<'_>>) -> Json<ConversionResponse>
 fn convert_iso(
     source_iso: PathBuf,
     dest_dir: PathBuf,
-    game_title: Option<String>,
-    trim_mode: String,
+    options: god::ConvertOptions,
     num_threads: usize,
     dry_run: bool,
 ) -> Result<(String, String, String, String), Error> {
@@ -519,20 +666,14 @@ fn convert_iso(
         ),
     }
 
-    let source_iso_file = File::open(&source_iso).context("error opening source ISO file")?;
-    let source_iso_file_meta =
-        fs::metadata(&source_iso).context("error reading source ISO file metadata")?;
-    let mut source_iso_reader =
-        iso::IsoReader::read(source_iso_file).context("error reading source ISO")?;
+    let converter = god::Converter::open(&source_iso)?;
 
-    let title_info =
-        TitleInfo::from_image(&mut source_iso_reader).context("error reading image executable")?;
-    let exe_info = title_info.execution_info;
+    let title_info = converter.title_info();
+    let exe_info = &title_info.execution_info;
     let content_type = title_info.content_type;
 
     let title_id = format!("{:08X}", exe_info.title_id);
-    let game_name =
-        game_list::find_title_by_id(exe_info.title_id).unwrap_or("(unknown)".to_owned());
+    let game_name = title_info.game_title().unwrap_or("(unknown)".to_owned());
 
     let title_id_str = {
         let mut result = String::new();
@@ -542,6 +683,24 @@ fn convert_iso(
             ContentType::GamesOnDemand => result.push_str("    Type: Games on Demand\n"),
             ContentType::XboxOriginal => result.push_str("    Type: Xbox Original\n"),
         }
+        match &title_info.game_icon {
+            Some(game_icon) => result.push_str(&format!("    Icon: {} bytes\n", game_icon.len())),
+            None => result.push_str("    Icon: (none)\n"),
+        }
+
+        let health = converter.health();
+        if health.trailing_size > 0 {
+            result.push_str(&format!(
+                "Warning: the ISO image has {} bytes of trailing junk\n",
+                health.trailing_size
+            ));
+        }
+        if health.is_truncated() {
+            result.push_str(&format!(
+                "Warning: the ISO image is truncated, {} bytes are missing\n",
+                health.missing_size()
+            ));
+        }
         result
     };
 
@@ -550,133 +709,146 @@ fn convert_iso(
         return Ok((title_id_str, String::new(), game_name, title_id));
     }
 
-    let data_size = if trim_mode == "from-end" {
-        source_iso_reader.get_max_used_prefix_size()
-    } else {
-        let root_offset = source_iso_reader.volume_descriptor.root_offset;
-        source_iso_file_meta.len() - root_offset
-    };
-
-    let block_count = data_size.div_ceil(god::BLOCK_SIZE);
-    let part_count = block_count.div_ceil(god::BLOCKS_PER_PART);
-
-    let file_layout = god::FileLayout::new(&dest_dir, &exe_info, content_type);
+    let result = converter.convert(&dest_dir, &options, &WebProgress)?;
 
-    ensure_empty_dir(&file_layout.data_dir_path()).context("error clearing data directory")?;
-
-    let progress = AtomicUsize::new(0);
-
-    (0..part_count).into_par_iter().try_for_each(|part_index| {
-        let mut iso_data_volume = File::open(&source_iso)?;
-        iso_data_volume.seek(SeekFrom::Start(
-            source_iso_reader.volume_descriptor.root_offset,
-        ))?;
-
-        let part_file = file_layout.part_file_path(part_index);
+    // The GOD path is the title directory (base_path/title_id)
+    let god_path = result.title_dir_path.to_string_lossy().to_string();
 
-        let part_file = File::options()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&part_file)
-            .context("error creating part file")?;
+    Ok((
+        format!("{}Conversion successful!", title_id_str),
+        god_path,
+        game_name,
+        title_id,
+    ))
+}
 
-        god::write_part(iso_data_volume, part_index, part_file)
-            .context("error writing part file")?;
+struct WebProgress;
 
-        let cur = 1 + progress.fetch_add(1, Ordering::Relaxed);
-        eprintln!("writing part files: {cur:2}/{part_count}");
+impl god::ConvertProgress for WebProgress {
+    fn part_written(&self, parts_written: u64, part_count: u64) {
+        eprintln!("writing part files: {parts_written:2}/{part_count}");
+    }
+}
 
-        Ok::<_, anyhow::Error>(())
-    })?;
+/// Change the title, icon or disc number of an already converted game
+#[post("/edit-god", data = "<form>")]
+async fn edit_god(mut form: Form<EditGodForm<'_>>) -> Json<EditGodResponse> {
+    // Validate god_path - prevent path traversal
+    if form.god_path.contains("..") {
+        return Json(EditGodResponse {
+            success: false,
+            message: "Invalid GOD path: path traversal not allowed".to_string(),
+            game_title: None,
+        });
+    }
 
-    let mut mht =
-        read_part_mht(&file_layout, part_count - 1).context("error reading part file MHT")?;
+    let game_title = form.game_title.clone().filter(|t| !t.is_empty());
+    if let Some(Err(e)) = game_title.as_deref().map(god::check_game_title) {
+        return Json(EditGodResponse {
+            success: false,
+            message: format!("Invalid game title: {}", e),
+            game_title: None,
+        });
+    }
 
-    for prev_part_index in (0..part_count - 1).rev() {
-        let mut prev_mht =
-            read_part_mht(&file_layout, prev_part_index).context("error reading part file MHT")?;
+    // Browsers send an empty file part when no icon was chosen
+    let game_icon = match form.game_icon.as_mut().filter(|icon| icon.len() > 0) {
+        Some(uploaded_icon) => match read_uploaded_file(uploaded_icon).await {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                return Json(EditGodResponse {
+                    success: false,
+                    message: format!("Failed to read uploaded icon: {}", e),
+                    game_title: None,
+                });
+            }
+        },
+        None => None,
+    };
 
-        prev_mht.add_hash(&mht.digest());
+    let god_path = PathBuf::from(&form.god_path);
+    let edit = god::ConHeaderEdit {
+        game_title,
+        game_icon,
+        disc_number: form.disc_number,
+        disc_count: form.disc_count,
+    };
 
-        write_part_mht(&file_layout, prev_part_index, &prev_mht)
-            .context("error writing part file MHT")?;
+    let result = tokio::task::spawn_blocking(move || edit_god_headers(&god_path, &edit)).await;
 
-        mht = prev_mht;
+    match result {
+        Ok(Ok(game_title)) => Json(EditGodResponse {
+            success: true,
+            message: "CON header updated".to_string(),
+            game_title: Some(game_title),
+        }),
+        Ok(Err(e)) => Json(EditGodResponse {
+            success: false,
+            message: format!("{:#}", e),
+            game_title: None,
+        }),
+        Err(e) => Json(EditGodResponse {
+            success: false,
+            message: format!("Task execution failed: {}", e),
+            game_title: None,
+        }),
     }
+}
 
-    let last_part_size = fs::metadata(file_layout.part_file_path(part_count - 1))
-        .map(|m| m.len())
-        .context("error reading part file")?;
+async fn read_uploaded_file(uploaded_file: &mut TempFile<'_>) -> Result<Vec<u8>, Error> {
+    let temp_dir = tempdir()?;
+    let temp_path = temp_dir.path().join("upload");
+    uploaded_file.copy_to(&temp_path).await?;
+    Ok(fs::read(&temp_path)?)
+}
 
-    let mut con_header = god::ConHeaderBuilder::new()
-        .with_execution_info(&exe_info)
-        .with_block_counts(block_count as u32, 0)
-        .with_data_parts_info(
-            part_count as u32,
-            last_part_size + (part_count - 1) * god::BLOCK_SIZE * 0xa290,
-        )
-        .with_content_type(content_type)
-        .with_mht_hash(&mht.digest());
+fn edit_god_headers(god_path: &Path, edit: &god::ConHeaderEdit) -> Result<String, Error> {
+    let con_header_paths = find_con_headers(god_path);
 
-    let game_title_final = game_title.or(game_list::find_title_by_id(exe_info.title_id));
-    if let Some(game_title) = game_title_final {
-        con_header = con_header.with_game_title(&game_title);
+    if con_header_paths.is_empty() {
+        anyhow::bail!("no CON header found in {}", god_path.display());
     }
 
-    let con_header = con_header.finalize();
-
-    let mut con_header_file = File::options()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(file_layout.con_header_file_path())
-        .context("cannot open con header file")?;
-
-    con_header_file
-        .write_all(&con_header)
-        .context("error writing con header file")?;
+    // Titles and icons are shared by all discs, disc numbers are not
+    if con_header_paths.len() > 1 && (edit.disc_number.is_some() || edit.disc_count.is_some()) {
+        anyhow::bail!("cannot set the disc number for a title with several discs");
+    }
 
-    // The GOD path is the title directory (base_path/title_id)
-    let god_path = file_layout
-        .con_header_file_path()
-        .parent()
-        .and_then(|p| p.parent())
-        .unwrap()
-        .to_string_lossy()
-        .to_string();
+    let con_headers = con_header_paths
+        .iter()
+        .map(|con_header_path| {
+            let con_header_file =
+                File::open(con_header_path).context("error opening con header file")?;
+            god::ConHeader::read(con_header_file).context("error reading con header")
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let file_layouts: Vec<_> = con_header_paths
+        .iter()
+        .zip(&con_headers)
+        .map(|(con_header_path, con_header)| {
+            god::FileLayout::new(
+                god::FileLayout::base_path_of(con_header_path),
+                &con_header.execution_info,
+                con_header.content_type,
+            )
+        })
+        .collect();
 
-    Ok((
-        format!("{}Conversion successful!", title_id_str),
-        god_path,
-        game_name,
-        title_id,
-    ))
-}
+    // every disc is checked before any of them is written, so that a failed
+    // edit leaves all of them as they were
+    let buffers = file_layouts
+        .iter()
+        .map(|file_layout| god::build_edited_con_header(file_layout, edit))
+        .collect::<Result<Vec<_>, Error>>()?;
 
-fn ensure_empty_dir(path: &Path) -> Result<(), Error> {
-    if fs::exists(path)? {
-        fs::remove_dir_all(path)?;
-    };
-    fs::create_dir_all(path)?;
-    Ok(())
-}
+    let mut game_title = String::new();
 
-fn read_part_mht(file_layout: &god::FileLayout, part_index: u64) -> Result<god::HashList, Error> {
-    let part_file = file_layout.part_file_path(part_index);
-    let mut part_file = File::options().read(true).open(part_file)?;
-    god::HashList::read(&mut part_file)
-}
+    for (file_layout, buffer) in file_layouts.iter().zip(&buffers) {
+        game_title = god::write_con_header(file_layout, buffer)?.game_title;
+    }
 
-fn write_part_mht(
-    file_layout: &god::FileLayout,
-    part_index: u64,
-    mht: &god::HashList,
-) -> Result<(), Error> {
-    let part_file = file_layout.part_file_path(part_index);
-    let mut part_file = File::options().write(true).open(part_file)?;
-    mht.write(&mut part_file)?;
-    Ok(())
+    Ok(game_title)
 }
 
 /// Test FTP connection without transferring any files
@@ -1011,6 +1183,7 @@ fn rocket() -> rocket::Rocket<rocket::Build> {
                 list_converted_games,
                 get_iso_info,
                 convert,
+                edit_god,
                 ftp_test,
                 ftp_transfer,
                 ftp_progress
@@ -1,38 +1,78 @@
-use std::io::{Seek, SeekFrom, Write};
-
-use std::fs;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::BufWriter;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
-
-use anyhow::{Context, Error};
 
-use clap::{Parser, ValueEnum};
+use anyhow::{Context, Error, bail};
 
-use rayon::prelude::*;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
-use iso2god::executable::TitleInfo;
+use iso2god::god;
 use iso2god::god::ContentType;
-use iso2god::{game_list, god, iso};
+use iso2god::iso;
+use iso2god::iso::iso_type::IsoType;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(color = clap::ColorChoice::Never)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    convert: ConvertArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a GOD package back to an ISO image
+    God2iso(God2IsoArgs),
+
+    /// Check all hashes in a GOD package
+    Verify(VerifyArgs),
+
+    /// Print the metadata from a GOD package CON header
+    Info(InfoArgs),
+
+    /// Change the metadata in a GOD package CON header, without reconverting
+    Edit(EditArgs),
+
+    /// Extract the files from an ISO image to a folder
+    Extract(ExtractArgs),
+
+    /// Build an ISO image from a folder
+    Dir2iso(Dir2IsoArgs),
+
+    /// Print the partition layout of an ISO image, and how much trimming saves
+    Analyze(AnalyzeArgs),
+}
+
+#[derive(Args)]
+struct ConvertArgs {
     /// ISO file to convert
-    source_iso: PathBuf,
+    #[arg(required = true)]
+    source_iso: Option<PathBuf>,
 
     /// A folder to write resulting GOD files to
-    dest_dir: PathBuf,
+    #[arg(required = true)]
+    dest_dir: Option<PathBuf>,
 
     /// Do not convert anything, just print the title info
     #[arg(long)]
     dry_run: bool,
 
+    /// Convert a truncated ISO image anyway, padding the missing data with zeroes
+    #[arg(long)]
+    force: bool,
+
     /// Set game title
     #[arg(long, value_name = "TITLE")]
     game_title: Option<String>,
 
+    /// Set game icon, instead of the one from the executable (PNG, at most 16 KiB)
+    #[arg(long, value_name = "PNG")]
+    game_icon: Option<PathBuf>,
+
     /// Whether to trim off unused space from the ISO image;
     /// passing no --trim flag at all is equivalent to "from-end"
     #[arg(
@@ -58,13 +98,158 @@ enum TrimMode {
 
     /// Trim nothing
     None,
-    // TODO
-    // FullRebuild,
+
+    /// Rebuild the filesystem, packing all files together without gaps
+    FullRebuild,
+}
+
+impl From<TrimMode> for god::TrimMode {
+    fn from(value: TrimMode) -> Self {
+        match value {
+            TrimMode::FromEnd => god::TrimMode::FromEnd,
+            TrimMode::None => god::TrimMode::None,
+            TrimMode::FullRebuild => god::TrimMode::FullRebuild,
+        }
+    }
+}
+
+#[derive(Args)]
+struct God2IsoArgs {
+    /// CON header file of the GOD package
+    con_header: PathBuf,
+
+    /// ISO file to write
+    dest_iso: PathBuf,
+
+    /// Layout of the resulting ISO image
+    #[arg(long, value_enum, default_value_t)]
+    iso_type: OutputIsoType,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// CON header file of the GOD package
+    con_header: PathBuf,
+
+    /// Number of worker threads to use
+    #[arg(long, short = 'j', value_name = "N", default_value_t = 1)]
+    num_threads: usize,
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    /// CON header file of the GOD package
+    con_header: PathBuf,
+}
+
+#[derive(Args)]
+struct EditArgs {
+    /// CON header file of the GOD package
+    con_header: PathBuf,
+
+    /// Set game title
+    #[arg(long, value_name = "TITLE")]
+    game_title: Option<String>,
+
+    /// Set game icon from a PNG file
+    #[arg(long, value_name = "PNG")]
+    game_icon: Option<PathBuf>,
+
+    /// Set disc number
+    #[arg(long, value_name = "N")]
+    disc_number: Option<u8>,
+
+    /// Set total disc count
+    #[arg(long, value_name = "N")]
+    disc_count: Option<u8>,
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    /// ISO file to extract, or the CON header file of a GOD package
+    source_iso: PathBuf,
+
+    /// A folder to write the files to
+    dest_dir: PathBuf,
+
+    /// Only extract files whose path matches this glob, like "media/**" or "*.xex";
+    /// can be given several times
+    #[arg(verbatim_doc_comment, long, value_name = "GLOB")]
+    filter: Vec<glob::Pattern>,
+
+    /// Skip files whose path matches this glob, even if they match a filter;
+    /// can be given several times
+    #[arg(verbatim_doc_comment, long, value_name = "GLOB")]
+    exclude: Vec<glob::Pattern>,
+
+    /// Number of worker threads to use
+    #[arg(long, short = 'j', value_name = "N", default_value_t = 1)]
+    num_threads: usize,
+}
+
+#[derive(Args)]
+struct Dir2IsoArgs {
+    /// Folder with the files to put into the image
+    source_dir: PathBuf,
+
+    /// ISO file to write
+    dest_iso: PathBuf,
+}
+
+#[derive(Args)]
+struct AnalyzeArgs {
+    /// ISO file to analyze; security sector, PFI and DMI dumps next to it
+    /// (like game.ss, game.pfi, game.dmi) are read as well
+    #[arg(verbatim_doc_comment)]
+    source_iso: PathBuf,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+enum OutputIsoType {
+    /// (default) Game partition only, as used by emulators and extracted-ISO tools
+    #[default]
+    Xsf,
+
+    /// Original Xbox disc layout, with a zeroed video partition
+    Xgd1,
+
+    /// Xbox 360 XGD2 disc layout, with a zeroed video partition
+    Xgd2,
+
+    /// Xbox 360 XGD3 disc layout, with a zeroed video partition
+    Xgd3,
+}
+
+impl From<OutputIsoType> for IsoType {
+    fn from(value: OutputIsoType) -> Self {
+        match value {
+            OutputIsoType::Xsf => IsoType::Xsf,
+            OutputIsoType::Xgd1 => IsoType::Xgd1,
+            OutputIsoType::Xgd2 => IsoType::Xgd2,
+            OutputIsoType::Xgd3 => IsoType::Xgd3,
+        }
+    }
 }
 
 fn main() -> Result<(), Error> {
     let args = Cli::parse();
 
+    match args.command {
+        Some(Command::God2iso(args)) => god2iso(args),
+        Some(Command::Verify(args)) => verify(args),
+        Some(Command::Info(args)) => info(args),
+        Some(Command::Edit(args)) => edit(args),
+        Some(Command::Extract(args)) => extract(args),
+        Some(Command::Dir2iso(args)) => dir2iso(args),
+        Some(Command::Analyze(args)) => analyze(args),
+        None => convert(args.convert),
+    }
+}
+
+fn convert(args: ConvertArgs) -> Result<(), Error> {
+    let source_iso_path = args.source_iso.context("missing source ISO")?;
+    let dest_dir = args.dest_dir.context("missing destination folder")?;
+
     if args.num_threads == 1 {
         eprintln!(
             "The default number of threads was changed to 1 because of the problems witn Windows and/or hard drives."
@@ -80,23 +265,15 @@ fn main() -> Result<(), Error> {
 
     println!("extracting ISO metadata");
 
-    let source_iso_file = File::open(&args.source_iso).context("error opening source ISO file")?;
-
-    let source_iso_file_meta =
-        fs::metadata(&args.source_iso).context("error reading source ISO file metadata")?;
-
-    let mut source_iso =
-        iso::IsoReader::read(source_iso_file).context("error reading source ISO")?;
+    let converter = god::Converter::open(&source_iso_path)?;
 
-    let title_info =
-        TitleInfo::from_image(&mut source_iso).context("error reading image executable")?;
-
-    let exe_info = title_info.execution_info;
+    let title_info = converter.title_info();
+    let exe_info = &title_info.execution_info;
     let content_type = title_info.content_type;
 
     {
         let title_id = format!("{:08X}", exe_info.title_id);
-        let name = game_list::find_title_by_id(exe_info.title_id).unwrap_or("(unknown)".to_owned());
+        let name = title_info.game_title().unwrap_or("(unknown)".to_owned());
 
         println!("Title ID: {title_id}");
         println!("    Name: {name}");
@@ -104,133 +281,327 @@ fn main() -> Result<(), Error> {
             ContentType::GamesOnDemand => println!("    Type: Games on Demand"),
             ContentType::XboxOriginal => println!("    Type: Xbox Original"),
         }
+        match &title_info.game_icon {
+            Some(game_icon) => println!("    Icon: {} bytes", game_icon.len()),
+            None => println!("    Icon: (none)"),
+        }
+
+        // together, these tell apart different releases of the same title
+        println!("Media ID: {:08X}", exe_info.media_id);
+        let image_creation_time = converter.source_iso().volume_descriptor.image_creation_time;
+        if image_creation_time.is_zero() {
+            println!(" Created: (unknown)");
+        } else {
+            println!(" Created: {image_creation_time}");
+        }
+    }
+
+    let health = converter.health();
+
+    if health.trailing_size > 0 {
+        println!(
+            "warning: the ISO image has {} bytes of trailing junk",
+            health.trailing_size
+        );
+    }
+
+    if health.is_truncated() {
+        println!(
+            "the ISO image is truncated, {} bytes are missing from:",
+            health.missing_size()
+        );
+        for entry in health.truncated_entries.iter() {
+            println!("    {}", entry.path);
+        }
+
+        if !args.force && !args.dry_run {
+            bail!("refusing to convert a truncated ISO image; use --force to zero-pad it");
+        }
     }
 
     if args.dry_run {
         return Ok(());
     }
 
-    let data_size = if args.trim.unwrap_or_default() == TrimMode::FromEnd {
-        source_iso.get_max_used_prefix_size()
-    } else {
-        let root_offset = source_iso.volume_descriptor.root_offset;
-        source_iso_file_meta.len() - root_offset
+    let game_icon = match &args.game_icon {
+        Some(path) => Some(fs::read(path).context("error reading game icon file")?),
+        None => None,
     };
 
-    let block_count = data_size.div_ceil(god::BLOCK_SIZE);
-    let part_count = block_count.div_ceil(god::BLOCKS_PER_PART);
+    let options = god::ConvertOptions {
+        trim_mode: args.trim.unwrap_or_default().into(),
+        game_title: args.game_title,
+        game_icon,
+        force: args.force,
+    };
 
-    let file_layout = god::FileLayout::new(&args.dest_dir, &exe_info, content_type);
+    let result = converter.convert(&dest_dir, &options, &CliProgress)?;
+
+    if options.trim_mode == god::TrimMode::FullRebuild {
+        let from_end_size = converter.data_size(god::TrimMode::FromEnd)?;
+        let saved_size = from_end_size.saturating_sub(result.data_size);
+        println!("full rebuild saved {saved_size} bytes compared to from-end");
+    }
 
-    println!("clearing data directory");
+    println!("done");
 
-    ensure_empty_dir(&file_layout.data_dir_path()).context("error clearing data directory")?;
+    Ok(())
+}
 
-    println!("writing part files:  0/{part_count}");
+struct CliProgress;
+
+impl god::ConvertProgress for CliProgress {
+    fn stage(&self, stage: god::ConvertStage) {
+        match stage {
+            god::ConvertStage::RebuildingFilesystem => println!("rebuilding filesystem"),
+            god::ConvertStage::ClearingDataDirectory => println!("clearing data directory"),
+            god::ConvertStage::WritingParts { part_count } => {
+                println!("writing part files:  0/{part_count}")
+            }
+            god::ConvertStage::CalculatingMhtHashChain => println!("calculating MHT hash chain"),
+            god::ConvertStage::WritingConHeader => println!("writing con header"),
+        }
+    }
 
-    let progress = AtomicUsize::new(0);
+    fn part_written(&self, parts_written: u64, part_count: u64) {
+        println!("writing part files: {parts_written:2}/{part_count}");
+    }
+}
 
-    (0..part_count).into_par_iter().try_for_each(|part_index| {
-        let mut iso_data_volume = File::open(&args.source_iso)?;
-        iso_data_volume.seek(SeekFrom::Start(source_iso.volume_descriptor.root_offset))?;
+fn god2iso(args: God2IsoArgs) -> Result<(), Error> {
+    println!("reading con header");
 
-        let part_file = file_layout.part_file_path(part_index);
+    let con_header = read_con_header(&args.con_header)?;
+    let file_layout = god::FileLayout::new(
+        god::FileLayout::base_path_of(&args.con_header),
+        &con_header.execution_info,
+        con_header.content_type,
+    );
 
-        let part_file = File::options()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&part_file)
-            .context("error creating part file")?;
+    println!("writing ISO image");
 
-        god::write_part(iso_data_volume, part_index, part_file)
-            .context("error writing part file")?;
+    let iso_file = File::options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&args.dest_iso)
+        .context("error creating destination ISO file")?;
+
+    let image_size = god::god2iso(
+        &file_layout,
+        con_header.part_count as u64,
+        args.iso_type.into(),
+        iso_file,
+    )
+    .context("error writing ISO image")?;
+
+    println!("wrote {image_size} bytes");
+    println!("done");
 
-        let cur = 1 + progress.fetch_add(1, Ordering::Relaxed);
-        println!("writing part files: {cur:2}/{part_count}");
+    Ok(())
+}
 
-        Ok::<_, anyhow::Error>(())
-    })?;
+fn verify(args: VerifyArgs) -> Result<(), Error> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.num_threads)
+        .build_global()?;
 
-    println!("calculating MHT hash chain");
+    println!("reading con header");
 
-    let mut mht =
-        read_part_mht(&file_layout, part_count - 1).context("error reading part file MHT")?;
+    let con_header = read_con_header(&args.con_header)?;
+    let file_layout = god::FileLayout::new(
+        god::FileLayout::base_path_of(&args.con_header),
+        &con_header.execution_info,
+        con_header.content_type,
+    );
 
-    for prev_part_index in (0..part_count - 1).rev() {
-        let mut prev_mht =
-            read_part_mht(&file_layout, prev_part_index).context("error reading part file MHT")?;
+    println!("verifying part files");
 
-        prev_mht.add_hash(&mht.digest());
+    let report = god::verify(&file_layout).context("error verifying GOD package")?;
 
-        write_part_mht(&file_layout, prev_part_index, &prev_mht)
-            .context("error writing part file MHT")?;
+    for corruption in report.corruptions.iter() {
+        println!("{corruption}");
+    }
 
-        mht = prev_mht;
+    if !report.is_ok() {
+        bail!(
+            "GOD package is corrupt: {} problems found",
+            report.corruptions.len()
+        );
     }
 
-    let last_part_size = fs::metadata(file_layout.part_file_path(part_count - 1))
-        .map(|m| m.len())
-        .context("error reading part file")?;
+    println!("all {} part files are OK", report.part_count);
 
-    println!("writing con header");
+    Ok(())
+}
 
-    let mut con_header = god::ConHeaderBuilder::new()
-        .with_execution_info(&exe_info)
-        .with_block_counts(block_count as u32, 0)
-        .with_data_parts_info(
-            part_count as u32,
-            last_part_size + (part_count - 1) * god::BLOCK_SIZE * 0xa290,
-        )
-        .with_content_type(content_type)
-        .with_mht_hash(&mht.digest());
+fn info(args: InfoArgs) -> Result<(), Error> {
+    let con_header = read_con_header(&args.con_header)?;
+    let exe_info = &con_header.execution_info;
 
-    let game_title = args
-        .game_title
-        .or(game_list::find_title_by_id(exe_info.title_id));
-    if let Some(game_title) = game_title {
-        con_header = con_header.with_game_title(&game_title);
+    println!("Title ID: {:08X}", exe_info.title_id);
+    println!("    Name: {}", con_header.game_title);
+    match con_header.content_type {
+        ContentType::GamesOnDemand => println!("    Type: Games on Demand"),
+        ContentType::XboxOriginal => println!("    Type: Xbox Original"),
     }
+    println!("Media ID: {:08X}", exe_info.media_id);
+    println!("    Disc: {}/{}", exe_info.disc_number, exe_info.disc_count);
+    println!("   Parts: {}", con_header.part_count);
+    println!("  Blocks: {}", con_header.blocks_allocated);
+    println!("    Icon: {} bytes", con_header.game_icon.len());
 
-    let con_header = con_header.finalize();
+    Ok(())
+}
 
-    let mut con_header_file = File::options()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(file_layout.con_header_file_path())
-        .context("cannot open con header file")?;
+fn edit(args: EditArgs) -> Result<(), Error> {
+    let con_header = read_con_header(&args.con_header)?;
+    let file_layout = god::FileLayout::new(
+        god::FileLayout::base_path_of(&args.con_header),
+        &con_header.execution_info,
+        con_header.content_type,
+    );
+
+    let game_icon = match &args.game_icon {
+        Some(path) => Some(fs::read(path).context("error reading game icon file")?),
+        None => None,
+    };
+
+    let edit = god::ConHeaderEdit {
+        game_title: args.game_title,
+        game_icon,
+        disc_number: args.disc_number,
+        disc_count: args.disc_count,
+    };
+
+    println!("writing con header");
 
-    con_header_file
-        .write_all(&con_header)
-        .context("error writing con header file")?;
+    god::edit_con_header(&file_layout, &edit).context("error editing con header")?;
 
     println!("done");
 
     Ok(())
 }
 
-fn ensure_empty_dir(path: &Path) -> Result<(), Error> {
-    if fs::exists(path)? {
-        fs::remove_dir_all(path)?;
+fn extract(args: ExtractArgs) -> Result<(), Error> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.num_threads)
+        .build_global()?;
+
+    println!("extracting files");
+
+    let options = iso::ExtractOptions {
+        filters: args.filter,
+        excludes: args.exclude,
     };
-    fs::create_dir_all(path)?;
+
+    let result = iso::extract(&args.source_iso, &args.dest_dir, &options)
+        .context("error extracting ISO image")?;
+
+    println!(
+        "extracted {} files, {} bytes",
+        result.file_count, result.total_size
+    );
+    println!("done");
+
     Ok(())
 }
 
-fn read_part_mht(file_layout: &god::FileLayout, part_index: u64) -> Result<god::HashList, Error> {
-    let part_file = file_layout.part_file_path(part_index);
-    let mut part_file = File::options().read(true).open(part_file)?;
-    god::HashList::read(&mut part_file)
+fn dir2iso(args: Dir2IsoArgs) -> Result<(), Error> {
+    println!("reading source folder");
+
+    let image =
+        iso::DirectoryImage::new(&args.source_dir).context("error reading source folder")?;
+
+    println!("writing ISO image");
+
+    let iso_file = File::options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&args.dest_iso)
+        .context("error creating destination ISO file")?;
+
+    image
+        .write(BufWriter::new(iso_file))
+        .context("error writing ISO image")?;
+
+    println!("wrote {} bytes", image.size);
+    println!("done");
+
+    Ok(())
 }
 
-fn write_part_mht(
-    file_layout: &god::FileLayout,
-    part_index: u64,
-    mht: &god::HashList,
-) -> Result<(), Error> {
-    let part_file = file_layout.part_file_path(part_index);
-    let mut part_file = File::options().write(true).open(part_file)?;
-    mht.write(&mut part_file)?;
+fn analyze(args: AnalyzeArgs) -> Result<(), Error> {
+    let source_iso_file =
+        iso::ImageReader::open(&args.source_iso).context("error opening source ISO file")?;
+    let source_iso = iso::IsoReader::read(source_iso_file).context("error reading source ISO")?;
+
+    let mut image_info = iso::XgdImageInfo::read(&source_iso).context("error analyzing image")?;
+    image_info
+        .read_metadata_files(&args.source_iso)
+        .context("error reading disc metadata files")?;
+
+    let layout = match image_info.iso_type {
+        IsoType::Xgd1 => "XGD1 full disc image",
+        IsoType::Xgd2 => "XGD2 full disc image",
+        IsoType::Xgd3 => "XGD3 full disc image",
+        IsoType::Xsf => "game partition only (XSF)",
+    };
+
+    println!("         Layout: {layout}");
+    println!("     Image size: {} bytes", image_info.image_size);
+
+    if image_info.is_full_image() {
+        let video_data = if image_info.has_video_data {
+            ""
+        } else {
+            ", zeroed"
+        };
+        println!(
+            "Video partition: {} bytes{video_data}",
+            image_info.video_partition_size
+        );
+        println!(
+            "  Layer 1 video: {} bytes after the game partition",
+            image_info.video_layer1_size
+        );
+    }
+
+    println!(
+        " Game partition: {} bytes at {:#x}",
+        image_info.game_partition_size, image_info.game_partition_offset
+    );
+    println!(
+        "      Used size: {} bytes (trimming saves {} bytes)",
+        image_info.game_partition_used_size,
+        image_info.trim_savings()
+    );
+
+    if let Some(layer_break) = image_info.layer_break {
+        println!("    Layer break: {layer_break}");
+    }
+
+    if let Some(pfi) = &image_info.pfi {
+        println!(
+            "            PFI: book type {}, {} layer(s), {} sectors",
+            pfi.book_type,
+            pfi.layer_count,
+            pfi.sector_count()
+        );
+    }
+
+    let present = |present: bool| if present { "present" } else { "(none)" };
+    println!("            DMI: {}", present(image_info.dmi.is_some()));
+    println!(
+        "Security sector: {}",
+        present(image_info.security_sector.is_some())
+    );
+
     Ok(())
 }
+
+fn read_con_header(path: &Path) -> Result<god::ConHeader, Error> {
+    let con_header_file = File::open(path).context("error opening con header file")?;
+    god::ConHeader::read(con_header_file).context("error reading con header")
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+
+/// Failures that callers may want to handle differently, or show as more than
+/// a message.
+///
+/// Functions in this crate return [`anyhow::Error`], with one of these at the
+/// bottom of the context chain where it applies; use [`Iso2GodError::find`] to
+/// get it back out.
+#[derive(Debug)]
+pub enum Iso2GodError {
+    /// There is no Xbox volume descriptor at any of the known offsets
+    NotXboxImage,
+    /// The image has neither a default.xex nor a default.xbe
+    NoExecutable,
+    /// The executable header has no title ID, media ID, etc.
+    MissingExecutionInfo,
+    /// The image ends at `offset`, but `missing` more bytes are needed.
+    ///
+    /// Data that ends early while parsing a header is reported as a plain
+    /// [`std::io::ErrorKind::UnexpectedEof`] instead, which has the same code.
+    Truncated { offset: u64, missing: u64 },
+    /// The magic bytes of a header, `expected`, are not at `offset`, relative
+    /// to the start of the file the header was read from
+    BadMagic { offset: u64, expected: &'static str },
+}
+
+impl Iso2GodError {
+    /// A short identifier that stays the same across versions, unlike the
+    /// error message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Iso2GodError::NotXboxImage => "not_xbox_image",
+            Iso2GodError::NoExecutable => "no_executable",
+            Iso2GodError::MissingExecutionInfo => "missing_execution_info",
+            Iso2GodError::Truncated { .. } => "truncated",
+            Iso2GodError::BadMagic { .. } => "bad_magic",
+        }
+    }
+
+    /// The first of these in the chain of `error`.
+    pub fn find(error: &anyhow::Error) -> Option<&Iso2GodError> {
+        error.chain().find_map(|e| e.downcast_ref::<Iso2GodError>())
+    }
+
+    /// The code of the first of these in the chain of `error`, or else
+    /// `"truncated"` for an unexpected end of file, `"io"` for any other I/O
+    /// error, or `"other"`.
+    pub fn code_of(error: &anyhow::Error) -> &'static str {
+        for e in error.chain() {
+            if let Some(e) = e.downcast_ref::<Iso2GodError>() {
+                return e.code();
+            }
+            if let Some(e) = e.downcast_ref::<std::io::Error>() {
+                return match e.kind() {
+                    std::io::ErrorKind::UnexpectedEof => "truncated",
+                    _ => "io",
+                };
+            }
+        }
+
+        "other"
+    }
+}
+
+impl fmt::Display for Iso2GodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Iso2GodError::NotXboxImage => write!(f, "not an Xbox disc image"),
+            Iso2GodError::NoExecutable => write!(f, "no executable found in this image"),
+            Iso2GodError::MissingExecutionInfo => {
+                write!(f, "no execution info in the executable header")
+            }
+            Iso2GodError::Truncated { offset, missing } => write!(
+                f,
+                "data is truncated at offset {offset:#x}, {missing} bytes are missing"
+            ),
+            Iso2GodError::BadMagic { offset, expected } => {
+                write!(f, "missing {expected} magic bytes at offset {offset:#x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Iso2GodError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Context;
+
+    #[test]
+    fn each_variant_has_its_code() {
+        let cases = [
+            (Iso2GodError::NotXboxImage, "not_xbox_image"),
+            (Iso2GodError::NoExecutable, "no_executable"),
+            (Iso2GodError::MissingExecutionInfo, "missing_execution_info"),
+            (
+                Iso2GodError::Truncated {
+                    offset: 0x800,
+                    missing: 16,
+                },
+                "truncated",
+            ),
+            (
+                Iso2GodError::BadMagic {
+                    offset: 0,
+                    expected: "'XEX2'",
+                },
+                "bad_magic",
+            ),
+        ];
+
+        for (error, code) in cases {
+            assert_eq!(error.code(), code);
+
+            // also at the bottom of a context chain
+            let error = Err::<(), _>(error)
+                .context("error reading image")
+                .unwrap_err();
+            assert_eq!(Iso2GodError::code_of(&error), code);
+            assert_eq!(Iso2GodError::find(&error).unwrap().code(), code);
+        }
+    }
+
+    #[test]
+    fn io_errors_have_their_own_codes() {
+        let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+        let error = Err::<(), _>(eof)
+            .context("error reading header")
+            .context("error reading default.xex")
+            .unwrap_err();
+        assert_eq!(Iso2GodError::code_of(&error), "truncated");
+        assert!(Iso2GodError::find(&error).is_none());
+
+        let not_found = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let error = anyhow::Error::from(not_found);
+        assert_eq!(Iso2GodError::code_of(&error), "io");
+    }
+
+    #[test]
+    fn other_errors_are_other() {
+        let error = anyhow::anyhow!("something else").context("error converting");
+        assert_eq!(Iso2GodError::code_of(&error), "other");
+    }
+}
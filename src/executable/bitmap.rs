@@ -0,0 +1,107 @@
+use anyhow::Error;
+
+/// Size of the dashboard tile, in pixels
+const ICON_SIZE: usize = 64;
+
+/// An RGBA image decoded from an executable, to be turned into a game icon.
+#[derive(Clone, Debug)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl Bitmap {
+    pub fn new(width: usize, height: usize) -> Bitmap {
+        Bitmap {
+            width,
+            height,
+            rgba: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut [u8] {
+        let offset = (y * self.width + x) * 4;
+        &mut self.rgba[offset..offset + 4]
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> &[u8] {
+        let offset = (y * self.width + x) * 4;
+        &self.rgba[offset..offset + 4]
+    }
+
+    /// Scales the image down to fit into `width` x `height` while keeping the
+    /// aspect ratio, averaging the source pixels of every target pixel.
+    pub fn shrink_to_fit(&self, width: usize, height: usize) -> Bitmap {
+        if self.width <= width && self.height <= height {
+            return self.clone();
+        }
+
+        let (width, height) = if self.width * height > self.height * width {
+            (width, (self.height * width / self.width).max(1))
+        } else {
+            ((self.width * height / self.height).max(1), height)
+        };
+
+        let mut result = Bitmap::new(width, height);
+
+        for y in 0..height {
+            let (y0, y1) = (y * self.height / height, (y + 1) * self.height / height);
+
+            for x in 0..width {
+                let (x0, x1) = (x * self.width / width, (x + 1) * self.width / width);
+
+                let mut sum = [0usize; 4];
+                for sy in y0..y1.max(y0 + 1) {
+                    for sx in x0..x1.max(x0 + 1) {
+                        for (s, &c) in sum.iter_mut().zip(self.pixel(sx, sy)) {
+                            *s += c as usize;
+                        }
+                    }
+                }
+
+                let count = (y1.max(y0 + 1) - y0) * (x1.max(x0 + 1) - x0);
+                for (c, s) in result.pixel_mut(x, y).iter_mut().zip(sum) {
+                    *c = (s / count) as u8;
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Centers the image on a transparent canvas of the given size, which has
+    /// to be at least as large as the image.
+    pub fn pad_to(&self, width: usize, height: usize) -> Bitmap {
+        let mut result = Bitmap::new(width, height);
+        let (left, top) = ((width - self.width) / 2, (height - self.height) / 2);
+
+        for y in 0..self.height {
+            let row = &self.rgba[y * self.width * 4..(y + 1) * self.width * 4];
+            let offset = ((top + y) * width + left) * 4;
+            result.rgba[offset..offset + row.len()].copy_from_slice(row);
+        }
+
+        result
+    }
+
+    /// Encodes the image as a 64x64 PNG, suitable for a dashboard tile.
+    pub fn to_icon_png(&self) -> Result<Vec<u8>, Error> {
+        let icon = self
+            .shrink_to_fit(ICON_SIZE, ICON_SIZE)
+            .pad_to(ICON_SIZE, ICON_SIZE);
+
+        let mut png_bytes = Vec::new();
+
+        let mut encoder = png::Encoder::new(&mut png_bytes, icon.width as u32, icon.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(png::Compression::High);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&icon.rgba)?;
+        writer.finish()?;
+
+        Ok(png_bytes)
+    }
+}
@@ -0,0 +1,629 @@
+//! LZX decompression, as used by XEX basefiles.
+//!
+//! Based on the LZX decoder in libmspack (lzxd.c): frames of 32 KiB, no
+//! reset interval, and optional Intel E8 call translation.
+
+use anyhow::{Error, bail};
+
+const MIN_MATCH: usize = 2;
+const NUM_CHARS: usize = 256;
+const NUM_PRIMARY_LENGTHS: usize = 7;
+const NUM_SECONDARY_LENGTHS: usize = 249;
+const PRETREE_NUM_ELEMENTS: usize = 20;
+const ALIGNED_NUM_ELEMENTS: usize = 8;
+const MAX_POSITION_SLOTS: usize = 290;
+const MAINTREE_MAX_ELEMENTS: usize = NUM_CHARS + MAX_POSITION_SLOTS * 8;
+const FRAME_SIZE: usize = 0x8000;
+const MAX_CODE_LENGTH: u32 = 16;
+/// Room after each length table for runs that go past its end, which libmspack
+/// tolerates; the longest run is 51 lengths.
+const LENTABLE_SAFETY: usize = 64;
+
+const BLOCKTYPE_VERBATIM: u32 = 1;
+const BLOCKTYPE_ALIGNED: u32 = 2;
+const BLOCKTYPE_UNCOMPRESSED: u32 = 3;
+
+/// Decompresses a whole LZX stream into `output_size` bytes.
+pub fn decompress(input: &[u8], window_size: u32, output_size: usize) -> Result<Vec<u8>, Error> {
+    let Some(window_bits) = (15..=21).find(|&bits| 1 << bits == window_size) else {
+        bail!("unsupported LZX window size {window_size:#x}");
+    };
+
+    let mut decoder = Decoder::new(input, window_bits);
+    decoder.decompress(output_size)?;
+    Ok(decoder.output)
+}
+
+struct Decoder<'a> {
+    bits: BitReader<'a>,
+    output: Vec<u8>,
+
+    window_size: usize,
+    position_slots: usize,
+    position_base: Vec<u32>,
+    extra_bits: Vec<u8>,
+
+    r: [u32; 3],
+
+    block_type: u32,
+    block_length: usize,
+    block_remaining: usize,
+
+    intel_filesize: i32,
+    intel_started: bool,
+    intel_frames: Vec<usize>,
+
+    maintree_lens: Vec<u8>,
+    length_lens: Vec<u8>,
+    maintree: HuffmanTable,
+    length: HuffmanTable,
+    aligned: HuffmanTable,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(input: &'a [u8], window_bits: u32) -> Decoder<'a> {
+        let position_slots = match window_bits {
+            15 => 30,
+            16 => 32,
+            17 => 34,
+            18 => 36,
+            19 => 38,
+            20 => 42,
+            _ => 50,
+        };
+
+        let mut extra_bits = Vec::with_capacity(MAX_POSITION_SLOTS);
+        for i in 0..MAX_POSITION_SLOTS {
+            extra_bits.push((i.saturating_sub(2) / 2).min(17) as u8);
+        }
+
+        let mut position_base = Vec::with_capacity(MAX_POSITION_SLOTS);
+        let mut base = 0u32;
+        for &extra in &extra_bits {
+            position_base.push(base);
+            base = base.wrapping_add(1 << extra);
+        }
+
+        Decoder {
+            bits: BitReader::new(input),
+            output: Vec::new(),
+            window_size: 1 << window_bits,
+            position_slots,
+            position_base,
+            extra_bits,
+            r: [1, 1, 1],
+            block_type: 0,
+            block_length: 0,
+            block_remaining: 0,
+            intel_filesize: 0,
+            intel_started: false,
+            intel_frames: Vec::new(),
+            maintree_lens: vec![0; MAINTREE_MAX_ELEMENTS + LENTABLE_SAFETY],
+            length_lens: vec![0; NUM_SECONDARY_LENGTHS + LENTABLE_SAFETY],
+            maintree: HuffmanTable::default(),
+            length: HuffmanTable::default(),
+            aligned: HuffmanTable::default(),
+        }
+    }
+
+    fn decompress(&mut self, output_size: usize) -> Result<(), Error> {
+        self.output.reserve_exact(output_size);
+
+        if self.bits.read(1)? == 1 {
+            let high = self.bits.read(16)?;
+            let low = self.bits.read(16)?;
+            self.intel_filesize = ((high << 16) | low) as i32;
+        }
+
+        while self.output.len() < output_size {
+            let frame_start = self.output.len();
+            let frame_size = FRAME_SIZE.min(output_size - frame_start);
+
+            self.decode_frame(frame_start + frame_size)?;
+
+            if self.output.len() != frame_start + frame_size {
+                bail!("LZX match runs past the end of a frame");
+            }
+
+            // frames always start on a 16-bit boundary
+            self.bits.align();
+
+            if self.intel_started && self.intel_filesize != 0 && frame_size > 10 {
+                self.intel_frames.push(frame_start);
+            }
+        }
+
+        self.translate_intel_calls();
+
+        Ok(())
+    }
+
+    fn decode_frame(&mut self, frame_end: usize) -> Result<(), Error> {
+        while self.output.len() < frame_end {
+            if self.block_remaining == 0 {
+                self.read_block_header()?;
+            }
+
+            let run = self.block_remaining.min(frame_end - self.output.len());
+            let run_start = self.output.len();
+
+            match self.block_type {
+                BLOCKTYPE_VERBATIM | BLOCKTYPE_ALIGNED => self.decode_run(run_start + run)?,
+                BLOCKTYPE_UNCOMPRESSED => {
+                    let bytes = self.bits.read_bytes(run)?;
+                    self.output.extend_from_slice(bytes);
+                }
+                block_type => bail!("invalid LZX block type {block_type}"),
+            }
+
+            // the last match of a run can go further than asked for
+            let decoded = self.output.len() - run_start;
+            if decoded > self.block_remaining {
+                bail!("LZX match runs past the end of a block");
+            }
+            self.block_remaining -= decoded;
+        }
+
+        Ok(())
+    }
+
+    fn read_block_header(&mut self) -> Result<(), Error> {
+        if self.block_type == BLOCKTYPE_UNCOMPRESSED && self.block_length % 2 == 1 {
+            self.bits.read_bytes(1)?;
+        }
+
+        self.block_type = self.bits.read(3)?;
+        let high = self.bits.read(16)?;
+        let low = self.bits.read(8)?;
+        self.block_length = ((high << 8) | low) as usize;
+        self.block_remaining = self.block_length;
+
+        match self.block_type {
+            BLOCKTYPE_ALIGNED | BLOCKTYPE_VERBATIM => {
+                if self.block_type == BLOCKTYPE_ALIGNED {
+                    let mut aligned_lens = [0u8; ALIGNED_NUM_ELEMENTS];
+                    for len in aligned_lens.iter_mut() {
+                        *len = self.bits.read(3)? as u8;
+                    }
+                    self.aligned = HuffmanTable::build(&aligned_lens, false)?;
+                }
+
+                let maintree_elements = NUM_CHARS + self.position_slots * 8;
+
+                let mut maintree_lens = std::mem::take(&mut self.maintree_lens);
+                self.read_lens(&mut maintree_lens, 0, NUM_CHARS)?;
+                self.read_lens(&mut maintree_lens, NUM_CHARS, maintree_elements)?;
+                self.maintree = HuffmanTable::build(&maintree_lens[..maintree_elements], false)?;
+                if maintree_lens[0xe8] != 0 {
+                    self.intel_started = true;
+                }
+                self.maintree_lens = maintree_lens;
+
+                let mut length_lens = std::mem::take(&mut self.length_lens);
+                self.read_lens(&mut length_lens, 0, NUM_SECONDARY_LENGTHS)?;
+                self.length = HuffmanTable::build(&length_lens[..NUM_SECONDARY_LENGTHS], true)?;
+                self.length_lens = length_lens;
+            }
+            BLOCKTYPE_UNCOMPRESSED => {
+                self.intel_started = true;
+                self.bits.align_for_bytes();
+
+                let r = self.bits.read_bytes(12)?;
+                for (i, r_value) in self.r.iter_mut().enumerate() {
+                    *r_value = u32::from_le_bytes(r[i * 4..i * 4 + 4].try_into().unwrap());
+                }
+            }
+            block_type => bail!("invalid LZX block type {block_type}"),
+        }
+
+        Ok(())
+    }
+
+    /// Reads code lengths `first..last` as deltas from the previous ones, using
+    /// a pretree. The last run may go past `last`, into the next range or the
+    /// safety room at the end of `lens`.
+    fn read_lens(&mut self, lens: &mut [u8], first: usize, last: usize) -> Result<(), Error> {
+        debug_assert!(last + LENTABLE_SAFETY <= lens.len());
+
+        let mut pretree_lens = [0u8; PRETREE_NUM_ELEMENTS];
+        for len in pretree_lens.iter_mut() {
+            *len = self.bits.read(4)? as u8;
+        }
+        let pretree = HuffmanTable::build(&pretree_lens, false)?;
+
+        let delta = |len: u8, z: u16| ((len as i32 - z as i32).rem_euclid(17)) as u8;
+
+        let mut x = first;
+        while x < last {
+            let z = pretree.decode(&mut self.bits)?;
+
+            let (count, value) = match z {
+                17 => (self.bits.read(4)? as usize + 4, None),
+                18 => (self.bits.read(5)? as usize + 20, None),
+                19 => {
+                    let count = self.bits.read(1)? as usize + 4;
+                    let z = pretree.decode(&mut self.bits)?;
+                    if z > 16 {
+                        bail!("invalid LZX pretree symbol {z}");
+                    }
+                    (count, Some(delta(lens[x], z)))
+                }
+                z => (1, Some(delta(lens[x], z))),
+            };
+
+            lens[x..x + count].fill(value.unwrap_or(0));
+            x += count;
+        }
+
+        Ok(())
+    }
+
+    fn decode_run(&mut self, run_end: usize) -> Result<(), Error> {
+        while self.output.len() < run_end {
+            let main_element = self.maintree.decode(&mut self.bits)? as usize;
+
+            if main_element < NUM_CHARS {
+                self.output.push(main_element as u8);
+                continue;
+            }
+
+            let main_element = main_element - NUM_CHARS;
+
+            let mut match_length = main_element & NUM_PRIMARY_LENGTHS;
+            if match_length == NUM_PRIMARY_LENGTHS {
+                match_length += self.length.decode(&mut self.bits)? as usize;
+            }
+            match_length += MIN_MATCH;
+
+            let position_slot = main_element >> 3;
+            let match_offset = match position_slot {
+                0 => self.r[0],
+                1 => {
+                    self.r.swap(0, 1);
+                    self.r[0]
+                }
+                2 => {
+                    self.r.swap(0, 2);
+                    self.r[0]
+                }
+                _ => {
+                    let offset = self.read_match_offset(position_slot)?;
+                    self.r = [offset, self.r[0], self.r[1]];
+                    offset
+                }
+            } as usize;
+
+            if match_offset == 0 || match_offset > self.output.len() {
+                bail!("LZX match offset {match_offset} is out of range");
+            }
+            if match_offset > self.window_size {
+                bail!("LZX match offset {match_offset} is larger than the window");
+            }
+
+            let start = self.output.len() - match_offset;
+            for i in 0..match_length {
+                let b = self.output[start + i];
+                self.output.push(b);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_match_offset(&mut self, position_slot: usize) -> Result<u32, Error> {
+        let extra = self.extra_bits[position_slot] as u32;
+        let base = self.position_base[position_slot] - 2;
+
+        if self.block_type == BLOCKTYPE_ALIGNED && extra >= 3 {
+            let verbatim_bits = self.bits.read(extra - 3)?;
+            let aligned_bits = self.aligned.decode(&mut self.bits)? as u32;
+            Ok(base + (verbatim_bits << 3) + aligned_bits)
+        } else {
+            Ok(base + self.bits.read(extra)?)
+        }
+    }
+
+    /// Undoes the E8 (x86 CALL) translation on the frames that had it.
+    fn translate_intel_calls(&mut self) {
+        let filesize = self.intel_filesize;
+
+        for &frame_start in &self.intel_frames {
+            let frame_end = (frame_start + FRAME_SIZE).min(self.output.len());
+            let frame = &mut self.output[frame_start..frame_end];
+
+            let mut curpos = frame_start as i32;
+            let mut i = 0;
+
+            while i < frame.len() - 10 {
+                if frame[i] != 0xe8 {
+                    i += 1;
+                    curpos += 1;
+                    continue;
+                }
+
+                let abs_off = i32::from_le_bytes(frame[i + 1..i + 5].try_into().unwrap());
+                if abs_off >= -curpos && abs_off < filesize {
+                    let rel_off = if abs_off >= 0 {
+                        abs_off - curpos
+                    } else {
+                        // the file size comes from the stream and may be
+                        // negative, which wraps around the same as in libmspack
+                        abs_off.wrapping_add(filesize)
+                    };
+                    frame[i + 1..i + 5].copy_from_slice(&rel_off.to_le_bytes());
+                }
+
+                i += 5;
+                curpos += 5;
+            }
+        }
+    }
+}
+
+/// Reads bits MSB-first out of 16-bit little-endian words.
+struct BitReader<'a> {
+    input: &'a [u8],
+    /// Position of the next word to load into `buffer`
+    position: usize,
+    buffer: u64,
+    buffered: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            input,
+            position: 0,
+            buffer: 0,
+            buffered: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        while self.buffered <= 48 {
+            // reading past the end yields zeroes; a stream that really ends
+            // early is caught by the output size checks
+            let word = match self.input.get(self.position..self.position + 2) {
+                Some(word) => u16::from_le_bytes([word[0], word[1]]),
+                None => 0,
+            };
+            self.position += 2;
+            self.buffer |= (word as u64) << (48 - self.buffered);
+            self.buffered += 16;
+        }
+    }
+
+    fn peek(&mut self, n: u32) -> u32 {
+        self.refill();
+        (self.buffer >> (64 - n)) as u32
+    }
+
+    fn consume(&mut self, n: u32) {
+        self.buffer <<= n;
+        self.buffered -= n;
+    }
+
+    fn read(&mut self, n: u32) -> Result<u32, Error> {
+        if n == 0 {
+            return Ok(0);
+        }
+        let value = self.peek(n);
+        self.consume(n);
+        Ok(value)
+    }
+
+    /// Skips the rest of a partially read word.
+    fn align(&mut self) {
+        let n = self.buffered % 16;
+        self.consume(n);
+    }
+
+    /// Aligns to a word boundary by skipping 1 to 16 bits, then switches to
+    /// reading whole bytes.
+    fn align_for_bytes(&mut self) {
+        self.refill();
+        let n = match self.buffered % 16 {
+            0 => 16,
+            n => n,
+        };
+        self.consume(n);
+        self.unbuffer();
+    }
+
+    fn unbuffer(&mut self) {
+        self.position -= (self.buffered / 8) as usize;
+        self.buffer = 0;
+        self.buffered = 0;
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        self.unbuffer();
+
+        let Some(bytes) = self.input.get(self.position..self.position + n) else {
+            bail!("LZX stream is truncated");
+        };
+        self.position += n;
+        Ok(bytes)
+    }
+}
+
+/// A canonical Huffman code, decoded with a single lookup on 16 bits.
+#[derive(Default)]
+struct HuffmanTable {
+    lens: Vec<u8>,
+    symbols: Vec<u16>,
+}
+
+const NO_SYMBOL: u16 = u16::MAX;
+
+impl HuffmanTable {
+    fn build(lens: &[u8], allow_empty: bool) -> Result<HuffmanTable, Error> {
+        let mut symbols = vec![NO_SYMBOL; 1 << MAX_CODE_LENGTH];
+
+        if lens.iter().all(|&len| len == 0) {
+            if allow_empty {
+                return Ok(HuffmanTable {
+                    lens: lens.to_vec(),
+                    symbols,
+                });
+            }
+            bail!("empty LZX Huffman table");
+        }
+
+        let mut code = 0usize;
+        for len in 1..=MAX_CODE_LENGTH {
+            for (symbol, _) in lens.iter().enumerate().filter(|&(_, &l)| l as u32 == len) {
+                let fill = 1 << (MAX_CODE_LENGTH - len);
+                let start = code << (MAX_CODE_LENGTH - len);
+
+                if start + fill > symbols.len() {
+                    bail!("oversubscribed LZX Huffman table");
+                }
+                symbols[start..start + fill].fill(symbol as u16);
+                code += 1;
+            }
+            code <<= 1;
+        }
+
+        Ok(HuffmanTable {
+            lens: lens.to_vec(),
+            symbols,
+        })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, Error> {
+        let symbol = self.symbols[bits.peek(MAX_CODE_LENGTH) as usize];
+        if symbol == NO_SYMBOL {
+            bail!("invalid LZX Huffman code");
+        }
+        bits.consume(self.lens[symbol as usize] as u32);
+        Ok(symbol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes bits MSB-first into 16-bit little-endian words, the way
+    /// [`BitReader`] reads them.
+    #[derive(Default)]
+    struct BitWriter {
+        output: Vec<u8>,
+        word: u16,
+        bits: u32,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, n: u32, value: u32) {
+            for i in (0..n).rev() {
+                self.word = (self.word << 1) | ((value >> i) & 1) as u16;
+                self.bits += 1;
+                if self.bits == 16 {
+                    self.output.extend_from_slice(&self.word.to_le_bytes());
+                    self.word = 0;
+                    self.bits = 0;
+                }
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.bits > 0 {
+                self.write(16 - self.bits, 0);
+            }
+            self.output
+        }
+    }
+
+    /// Writes a pretree with 5-bit codes for all symbols, so that symbol `z`
+    /// is simply written as `z`.
+    fn write_pretree(bits: &mut BitWriter) {
+        for _ in 0..PRETREE_NUM_ELEMENTS {
+            bits.write(4, 5);
+        }
+    }
+
+    /// Writes code lengths from a table of all zeroes; `runs` are runs of
+    /// zeroes for the rest of the range.
+    fn write_lens(bits: &mut BitWriter, lens: &[u8], runs: &[u32]) {
+        write_pretree(bits);
+        for &len in lens {
+            bits.write(5, (17 - len as u32) % 17);
+        }
+        for &run in runs {
+            if run < 20 {
+                bits.write(5, 17);
+                bits.write(4, run - 4);
+            } else {
+                bits.write(5, 18);
+                bits.write(5, run - 20);
+            }
+        }
+    }
+
+    /// A verbatim block with "ab" as literals, followed by a 5-byte match at
+    /// offset 2.
+    fn verbatim_block(literal_runs: &[u32]) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+
+        // no E8 translation, then a verbatim block of 7 bytes
+        bits.write(1, 0);
+        bits.write(3, BLOCKTYPE_VERBATIM);
+        bits.write(16, 0);
+        bits.write(8, 7);
+
+        // 'a' gets code 0, 'b' code 10, and match element 291 (position slot
+        // 4, length header 3) code 11
+        let mut literal_lens = vec![0; 0x63];
+        literal_lens[0x61] = 1;
+        literal_lens[0x62] = 2;
+        write_lens(&mut bits, &literal_lens, literal_runs);
+
+        let mut match_lens = vec![0; 291 - NUM_CHARS + 1];
+        match_lens[291 - NUM_CHARS] = 2;
+        write_lens(&mut bits, &match_lens, &[51, 51, 51, 51]);
+
+        // no length tree
+        write_lens(&mut bits, &[], &[51, 51, 51, 51, 45]);
+
+        bits.write(1, 0b0);
+        bits.write(2, 0b10);
+        bits.write(2, 0b11);
+        // one extra bit for slot 4, for offset 2
+        bits.write(1, 0);
+
+        bits.finish()
+    }
+
+    #[test]
+    fn decompresses_verbatim_block() {
+        let input = verbatim_block(&[51, 51, 51, 4]);
+        let output = decompress(&input, 0x8000, 7).unwrap();
+        assert_eq!(output, b"abababa");
+    }
+
+    #[test]
+    fn allows_code_lengths_past_the_end_of_a_tree() {
+        // the last run goes 16 lengths past the literals, as libmspack allows
+        let input = verbatim_block(&[51, 51, 51, 20]);
+        let output = decompress(&input, 0x8000, 7).unwrap();
+        assert_eq!(output, b"abababa");
+    }
+
+    #[test]
+    fn decompresses_uncompressed_block() {
+        let mut bits = BitWriter::default();
+        bits.write(1, 0);
+        bits.write(3, BLOCKTYPE_UNCOMPRESSED);
+        bits.write(16, 0);
+        bits.write(8, 5);
+
+        let mut input = bits.finish();
+        // R0, R1 and R2, then the data
+        input.extend_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        input.extend_from_slice(b"hello");
+
+        let output = decompress(&input, 0x8000, 5).unwrap();
+        assert_eq!(output, b"hello");
+    }
+}
@@ -1,12 +1,27 @@
+use crate::Iso2GodError;
+use crate::game_list;
 use crate::god::ContentType;
 use crate::iso::IsoReader;
-use anyhow::{Context, Error, bail};
-use byteorder::{BE, LE, ReadBytesExt};
+use anyhow::{Context, Error};
+use byteorder::{BE, ReadBytesExt};
 use std::io::{Read, Seek, SeekFrom};
 
+mod bitmap;
 pub mod xbe;
+pub mod xdbf;
 pub mod xex;
 
+// only reachable through an executable otherwise, so the fuzz targets get to
+// call these directly
+#[cfg(fuzzing)]
+pub mod lzx;
+#[cfg(not(fuzzing))]
+mod lzx;
+#[cfg(fuzzing)]
+pub mod xpr;
+#[cfg(not(fuzzing))]
+mod xpr;
+
 #[derive(Clone, Debug)]
 pub struct TitleExecutionInfo {
     pub media_id: u32,
@@ -22,6 +37,13 @@ pub struct TitleExecutionInfo {
 pub struct TitleInfo {
     pub content_type: ContentType,
     pub execution_info: TitleExecutionInfo,
+    /// Dashboard tile from the executable, as PNG
+    pub game_icon: Option<Vec<u8>>,
+    /// Title data (SPA) embedded in the executable, with localized names,
+    /// images and achievements
+    pub xdbf: Option<xdbf::Xdbf>,
+    /// Certificate of an original Xbox executable
+    pub xbe_certificate: Option<xbe::XbeCertificate>,
 }
 
 impl TitleExecutionInfo {
@@ -38,39 +60,60 @@ impl TitleExecutionInfo {
         })
     }
 
-    pub fn from_xbe<R: Read + Seek>(mut reader: R) -> Result<TitleExecutionInfo, Error> {
-        reader.seek(SeekFrom::Current(8))?;
-        let title_id = reader.read_u32::<LE>()?;
-
-        reader.seek(SeekFrom::Current(164))?;
-        let version = reader.read_u32::<LE>()?;
+    pub fn from_xbe(certificate: &xbe::XbeCertificate) -> TitleExecutionInfo {
+        // disk numbers are zero-based, and the certificate has no disc count;
+        // the count is left at 1, as it can not be told from one disc alone
+        // (it can be set with `edit --disc-count`)
+        let disc_number =
+            u8::try_from(certificate.disk_number.saturating_add(1)).unwrap_or(u8::MAX);
 
-        Ok(TitleExecutionInfo {
+        TitleExecutionInfo {
+            // XBEs have no media ID; the GOD file layout uses the title ID instead
             media_id: 0,
-            version,
+            version: certificate.version,
             base_version: 0,
-            title_id,
+            title_id: certificate.title_id,
             platform: 0,
             executable_type: 0,
-            disc_number: 1,
+            disc_number,
             disc_count: 1,
-        })
+        }
     }
 }
 
 impl TitleInfo {
-    pub fn from_image<R: Read + Seek>(iso_image: &mut IsoReader<R>) -> Result<TitleInfo, Error> {
+    pub fn from_image<R: Read + Seek>(iso_image: &IsoReader<R>) -> Result<TitleInfo, Error> {
         if let Some(mut executable) = iso_image.get_entry(&"\\default.xex".into())? {
             let default_xex_header =
                 xex::XexHeader::read(&mut executable).context("error reading default.xex")?;
             let execution_info = default_xex_header
                 .fields
                 .execution_info
-                .context("no execution info in default.xex header")?;
+                .clone()
+                .ok_or(Iso2GodError::MissingExecutionInfo)
+                .context("error reading default.xex")?;
+
+            // the title data is only used for display, so any problem reading
+            // it is ignored
+            executable.rewind()?;
+            let xdbf = read_xex_xdbf(
+                &mut executable,
+                &default_xex_header,
+                execution_info.title_id,
+            )
+            .ok();
+
+            let game_icon = xdbf
+                .as_ref()
+                .and_then(|xdbf| xdbf.title_image())
+                .map(|icon| icon.to_vec());
 
             Ok(TitleInfo {
                 content_type: ContentType::GamesOnDemand,
                 execution_info,
+                game_icon,
+                xdbf,
+                xbe_certificate: None,
             })
         } else if let Some(mut executable) = iso_image.get_entry(&"\\default.xbe".into())? {
             let default_xbe_header =
@@ -78,14 +121,75 @@ impl TitleInfo {
             let execution_info = default_xbe_header
                 .fields
                 .execution_info
-                .context("no execution info in default.xbe header")?;
+                .clone()
+                .ok_or(Iso2GodError::MissingExecutionInfo)
+                .context("error reading default.xbe")?;
+
+            // same as for XEX, the icon is only used for display
+            executable.rewind()?;
+            let game_icon = read_xbe_icon(&mut executable, &default_xbe_header).ok();
 
             Ok(TitleInfo {
                 content_type: ContentType::XboxOriginal,
                 execution_info,
+                game_icon,
+                xdbf: None,
+                xbe_certificate: default_xbe_header.fields.certificate,
             })
         } else {
-            bail!("no executable found in this image");
+            Err(Iso2GodError::NoExecutable.into())
         }
     }
+
+    /// The title name from the built-in game list, or else the one from the
+    /// executable's own title data (in its default language) or certificate.
+    pub fn game_title(&self) -> Option<String> {
+        game_list::find_title_by_id(self.execution_info.title_id)
+            .or_else(|| {
+                let xdbf = self.xdbf.as_ref()?;
+                xdbf.title_name(xdbf.default_language().ok()?).ok()?
+            })
+            .or_else(|| {
+                let certificate = self.xbe_certificate.as_ref()?;
+                Some(certificate.title_name.clone()).filter(|name| !name.is_empty())
+            })
+    }
+}
+
+fn read_xex_xdbf<R: Read + Seek>(
+    reader: R,
+    header: &xex::XexHeader,
+    title_id: u32,
+) -> Result<xdbf::Xdbf, Error> {
+    let xdbf = header
+        .read_resource(reader, &format!("{title_id:08X}"))?
+        .context("no title resource in default.xex")?;
+
+    xdbf::Xdbf::parse(xdbf)
+}
+
+/// Converts the title image of an XBE to PNG, or the save image if there is
+/// no title image, or the logo bitmap if there are neither.
+fn read_xbe_icon<R: Read + Seek>(mut reader: R, header: &xbe::XbeHeader) -> Result<Vec<u8>, Error> {
+    let start = reader.stream_position()?;
+
+    for section_name in ["$$XTIMAGE", "$$XSIMAGE"] {
+        reader.seek(SeekFrom::Start(start))?;
+        let image = header
+            .read_section(&mut reader, section_name)
+            .ok()
+            .flatten()
+            .and_then(|xpr| xpr::decode(&xpr).ok());
+
+        if let Some(image) = image {
+            return image.to_icon_png();
+        }
+    }
+
+    reader.seek(SeekFrom::Start(start))?;
+    let logo = header
+        .read_logo_bitmap(&mut reader)?
+        .context("no title image or logo bitmap in default.xbe")?;
+
+    xbe::decode_logo_bitmap(&logo).to_icon_png()
 }
@@ -1,53 +1,410 @@
+use crate::Iso2GodError;
 use crate::executable::TitleExecutionInfo;
+use crate::executable::bitmap::Bitmap;
 use anyhow::{Error, bail};
-use byteorder::{LE, ReadBytesExt};
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LE, ReadBytesExt};
 use std::io::{Read, Seek, SeekFrom};
 
+bitflags! {
+    // based on https://xboxdevwiki.net/Xbe#Certificate
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct XbeMediaTypes: u32 {
+        const HARD_DISK = 0x00000001;
+        const DVD_X2 = 0x00000002;
+        const DVD_CD = 0x00000004;
+        const CD = 0x00000008;
+        const DVD_5_RO = 0x00000010;
+        const DVD_9_RO = 0x00000020;
+        const DVD_5_RW = 0x00000040;
+        const DVD_9_RW = 0x00000080;
+        const DONGLE = 0x00000100;
+        const MEDIA_BOARD = 0x00000200;
+        const NONSECURE_HARD_DISK = 0x40000000;
+        const NONSECURE_MODE = 0x80000000;
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct XbeRegion: u32 {
+        const NORTH_AMERICA = 0x00000001;
+        const JAPAN = 0x00000002;
+        const REST_OF_WORLD = 0x00000004;
+        const MANUFACTURING = 0x80000000;
+    }
+}
+
+const SECTION_HEADER_SIZE: u32 = 0x38;
+
+// sanity limits, real XBEs are well below them
+const MAX_HEADERS_SIZE: u32 = 0x0010_0000;
+const MAX_SECTION_COUNT: u32 = 0x0400;
+const MAX_SECTION_SIZE: u32 = 0x0100_0000;
+
+/// Size of the Microsoft logo bitmap
+pub const LOGO_WIDTH: usize = 100;
+pub const LOGO_HEIGHT: usize = 17;
+
 pub struct XbeHeader {
-    // We only need these fields to get the cert address
     pub dw_base_addr: u32,
     pub dw_certificate_addr: u32,
+    pub dw_logo_bitmap_addr: u32,
+    pub dw_logo_bitmap_size: u32,
     pub fields: XbeHeaderFields,
 }
 
 #[derive(Clone, Default, Debug)]
 pub struct XbeHeaderFields {
     pub execution_info: Option<TitleExecutionInfo>,
+    pub certificate: Option<XbeCertificate>,
+    pub sections: Vec<XbeSection>,
+}
+
+/// Based on https://xboxdevwiki.net/Xbe#Section_Headers
+#[derive(Clone, Debug)]
+pub struct XbeSection {
+    pub name: String,
+    pub flags: u32,
+    pub virtual_addr: u32,
+    pub virtual_size: u32,
+    /// Offset in the XBE file
+    pub raw_addr: u32,
+    pub raw_size: u32,
+    pub digest: [u8; 20],
+}
+
+/// Based on https://xboxdevwiki.net/Xbe#Certificate
+#[derive(Clone, Debug)]
+pub struct XbeCertificate {
+    pub time_date: u32,
+    pub title_id: u32,
+    pub title_name: String,
+    /// Other titles whose saves this one can read; unused slots are skipped
+    pub alternate_title_ids: Vec<u32>,
+    pub allowed_media: XbeMediaTypes,
+    pub game_region: XbeRegion,
+    /// ESRB rating: 0 = RP, 1 = AO, 2 = M, 3 = T, 4 = E, 5 = K-A, 6 = EC
+    pub game_ratings: u32,
+    /// Zero-based
+    pub disk_number: u32,
+    pub version: u32,
+    pub lan_key: [u8; 16],
+    pub signature_key: [u8; 16],
+}
+
+impl XbeCertificate {
+    pub fn read<R: Read>(mut reader: R) -> Result<XbeCertificate, Error> {
+        let _size = reader.read_u32::<LE>()?;
+        let time_date = reader.read_u32::<LE>()?;
+        let title_id = reader.read_u32::<LE>()?;
+
+        let mut title_name = [0u16; 40];
+        reader.read_u16_into::<LE>(&mut title_name)?;
+        let title_name = char::decode_utf16(title_name.into_iter().take_while(|&c| c != 0))
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect::<String>()
+            .trim_end()
+            .to_owned();
+
+        let mut alternate_title_ids = [0u32; 16];
+        reader.read_u32_into::<LE>(&mut alternate_title_ids)?;
+        let alternate_title_ids = alternate_title_ids
+            .into_iter()
+            .filter(|&id| id != 0)
+            .collect();
+
+        let allowed_media = XbeMediaTypes::from_bits_truncate(reader.read_u32::<LE>()?);
+        let game_region = XbeRegion::from_bits_truncate(reader.read_u32::<LE>()?);
+        let game_ratings = reader.read_u32::<LE>()?;
+        let disk_number = reader.read_u32::<LE>()?;
+        let version = reader.read_u32::<LE>()?;
+
+        let mut lan_key = [0u8; 16];
+        reader.read_exact(&mut lan_key)?;
+
+        let mut signature_key = [0u8; 16];
+        reader.read_exact(&mut signature_key)?;
+
+        Ok(XbeCertificate {
+            time_date,
+            title_id,
+            title_name,
+            alternate_title_ids,
+            allowed_media,
+            game_region,
+            game_ratings,
+            disk_number,
+            version,
+            lan_key,
+            signature_key,
+        })
+    }
+}
+
+impl XbeSection {
+    /// `None` if the header or the name is out of bounds.
+    fn parse(mut section_header: &[u8], headers: &[u8], base_addr: u32) -> Option<XbeSection> {
+        let flags = section_header.read_u32::<LE>().ok()?;
+        let virtual_addr = section_header.read_u32::<LE>().ok()?;
+        let virtual_size = section_header.read_u32::<LE>().ok()?;
+        let raw_addr = section_header.read_u32::<LE>().ok()?;
+        let raw_size = section_header.read_u32::<LE>().ok()?;
+        let name_addr = section_header.read_u32::<LE>().ok()?;
+        let _name_ref_count = section_header.read_u32::<LE>().ok()?;
+        let _head_shared_page_ref_count_addr = section_header.read_u32::<LE>().ok()?;
+        let _tail_shared_page_ref_count_addr = section_header.read_u32::<LE>().ok()?;
+
+        let mut digest = [0u8; 20];
+        section_header.read_exact(&mut digest).ok()?;
+
+        let name = headers.get(name_addr.checked_sub(base_addr)? as usize..)?;
+        let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        let name = String::from_utf8_lossy(&name[..name_len]).into_owned();
+
+        Some(XbeSection {
+            name,
+            flags,
+            virtual_addr,
+            virtual_size,
+            raw_addr,
+            raw_size,
+            digest,
+        })
+    }
 }
 
 impl XbeHeader {
     pub fn read<R: Read + Seek>(mut reader: R) -> Result<XbeHeader, Error> {
+        let offset = reader.stream_position()?;
+
         Self::check_magic_bytes(&mut reader)?;
 
-        // Offset 0x0104
-        reader.seek(SeekFrom::Current(256))?;
+        reader.seek(SeekFrom::Start(offset + 0x0104))?;
         let dw_base_addr = reader.read_u32::<LE>()?;
+        let dw_size_of_headers = reader.read_u32::<LE>()?;
 
-        // Offset 0x0118
-        reader.seek(SeekFrom::Current(16))?;
+        reader.seek(SeekFrom::Start(offset + 0x0118))?;
         let dw_certificate_addr = reader.read_u32::<LE>()?;
+        let dw_section_count = reader.read_u32::<LE>()?;
+        let dw_section_headers_addr = reader.read_u32::<LE>()?;
+
+        reader.seek(SeekFrom::Start(offset + 0x0170))?;
+        let dw_logo_bitmap_addr = reader.read_u32::<LE>()?;
+        let dw_logo_bitmap_size = reader.read_u32::<LE>()?;
+
+        // everything referenced from the header is within the headers, which
+        // are loaded at the base address
+        let mut headers = vec![0u8; dw_size_of_headers.min(MAX_HEADERS_SIZE) as usize];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut headers)?;
+
+        let header_slice = |addr: u32, size: u32| -> Result<&[u8], Error> {
+            let start = addr.checked_sub(dw_base_addr).map(|start| start as usize);
+            match start.and_then(|start| headers.get(start..start.checked_add(size as usize)?)) {
+                Some(slice) => Ok(slice),
+                None => bail!("XBE header address {addr:08X} is out of bounds"),
+            }
+        };
 
-        let offset = reader.stream_position()? - 284;
-        let cert_address = dw_certificate_addr - dw_base_addr;
-        reader.seek(SeekFrom::Start(offset + (cert_address as u64)))?;
+        let certificate = XbeCertificate::read(header_slice(dw_certificate_addr, 0x00d0)?)?;
+
+        // sections are only needed for the icon, so bad entries are skipped
+        // instead of failing the whole header
+        let sections = (0..dw_section_count.min(MAX_SECTION_COUNT))
+            .filter_map(|i| {
+                let addr = dw_section_headers_addr.checked_add(i * SECTION_HEADER_SIZE)?;
+                let section_header = header_slice(addr, SECTION_HEADER_SIZE).ok()?;
+                XbeSection::parse(section_header, &headers, dw_base_addr)
+            })
+            .collect();
 
         Ok(XbeHeader {
             dw_base_addr,
             dw_certificate_addr,
+            dw_logo_bitmap_addr,
+            dw_logo_bitmap_size,
             fields: XbeHeaderFields {
-                execution_info: Some(TitleExecutionInfo::from_xbe(reader)?),
+                execution_info: Some(TitleExecutionInfo::from_xbe(&certificate)),
+                certificate: Some(certificate),
+                sections,
             },
         })
     }
 
+    /// Reads the raw contents of a section. `reader` has to be at the start
+    /// of the XBE, same as for [`XbeHeader::read`].
+    pub fn read_section<R: Read + Seek>(
+        &self,
+        mut reader: R,
+        name: &str,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let Some(section) = self.fields.sections.iter().find(|s| s.name == name) else {
+            return Ok(None);
+        };
+
+        if section.raw_size > MAX_SECTION_SIZE {
+            bail!(
+                "XBE section {name} is too large: {} bytes",
+                section.raw_size
+            );
+        }
+
+        let Some(section_offset) = reader
+            .stream_position()?
+            .checked_add(section.raw_addr as u64)
+        else {
+            bail!("XBE section {name} is out of bounds");
+        };
+        reader.seek(SeekFrom::Start(section_offset))?;
+
+        let mut data = Vec::new();
+        reader
+            .take(section.raw_size as u64)
+            .read_to_end(&mut data)?;
+
+        if data.len() < section.raw_size as usize {
+            bail!("XBE section {name} is truncated");
+        }
+
+        Ok(Some(data))
+    }
+
+    /// Reads the run-length encoded Microsoft logo shown when the XBE starts.
+    /// `reader` has to be at the start of the XBE.
+    pub fn read_logo_bitmap<R: Read + Seek>(
+        &self,
+        mut reader: R,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        if self.dw_logo_bitmap_addr == 0 || self.dw_logo_bitmap_size == 0 {
+            return Ok(None);
+        }
+
+        let Some(logo_offset) = self.dw_logo_bitmap_addr.checked_sub(self.dw_base_addr) else {
+            bail!("XBE logo bitmap address is out of bounds");
+        };
+
+        if self.dw_logo_bitmap_size > MAX_HEADERS_SIZE {
+            bail!("XBE logo bitmap is too large");
+        }
+
+        let Some(logo_offset) = reader.stream_position()?.checked_add(logo_offset as u64) else {
+            bail!("XBE logo bitmap address is out of bounds");
+        };
+        reader.seek(SeekFrom::Start(logo_offset))?;
+
+        let mut data = vec![0u8; self.dw_logo_bitmap_size as usize];
+        reader.read_exact(&mut data)?;
+
+        Ok(Some(data))
+    }
+
     fn check_magic_bytes<R: Read + Seek>(mut reader: R) -> Result<(), Error> {
+        let offset = reader.stream_position()?;
+
         let mut magic_bytes = [0u8; 4];
         reader.read_exact(&mut magic_bytes)?;
 
         if &magic_bytes != b"XBEH" {
-            bail!("missing 'XBEH' magic bytes in XBE header");
+            return Err(Iso2GodError::BadMagic {
+                offset,
+                expected: "'XBEH'",
+            }
+            .into());
         }
 
         Ok(())
     }
 }
+
+/// Decodes the logo bitmap from [`XbeHeader::read_logo_bitmap`] into a
+/// grayscale image of [`LOGO_WIDTH`] x [`LOGO_HEIGHT`] pixels.
+pub(crate) fn decode_logo_bitmap(rle: &[u8]) -> Bitmap {
+    let mut bitmap = Bitmap::new(LOGO_WIDTH, LOGO_HEIGHT);
+    let mut pixels = bitmap.rgba.chunks_exact_mut(4);
+
+    let mut data = rle;
+    while let Some(&first) = data.first() {
+        // runs are either 8 bits (1, 3-bit length, 4-bit value) or 16 bits
+        // (0, 1, 10-bit length, 4-bit value), with the value as intensity
+        let (len, value) = if first & 1 != 0 {
+            data = &data[1..];
+            (((first >> 1) & 0x07) as usize, first >> 4)
+        } else if first & 2 != 0 && data.len() >= 2 {
+            let run = LE::read_u16(data);
+            data = &data[2..];
+            (((run >> 2) & 0x03ff) as usize, (run >> 12) as u8)
+        } else {
+            data = &data[1..];
+            continue;
+        };
+
+        for pixel in pixels.by_ref().take(len) {
+            let intensity = value * 17;
+            pixel.copy_from_slice(&[intensity, intensity, intensity, 0xff]);
+        }
+    }
+
+    bitmap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn bad_section_headers_are_skipped() {
+        const BASE_ADDR: u32 = 0x0001_0000;
+
+        let mut xbe = vec![0u8; 0x1000];
+        xbe[0..4].copy_from_slice(b"XBEH");
+        LE::write_u32(&mut xbe[0x104..], BASE_ADDR);
+        LE::write_u32(&mut xbe[0x108..], 0x1000);
+        LE::write_u32(&mut xbe[0x118..], BASE_ADDR + 0x200);
+        // two sections, the second one past the end of the headers
+        LE::write_u32(&mut xbe[0x11c..], 2);
+        LE::write_u32(&mut xbe[0x120..], BASE_ADDR + 0x1000 - SECTION_HEADER_SIZE);
+        // the name of the first one is out of bounds as well
+        LE::write_u32(
+            &mut xbe[0x1000 - SECTION_HEADER_SIZE as usize + 0x14..],
+            0x10,
+        );
+
+        LE::write_u32(&mut xbe[0x208..], 0x4d530004);
+
+        let header = XbeHeader::read(Cursor::new(xbe)).unwrap();
+        assert_eq!(header.fields.certificate.unwrap().title_id, 0x4d530004);
+        assert!(header.fields.sections.is_empty());
+    }
+
+    #[test]
+    fn logo_bitmap_runs_are_decoded() {
+        // 3 pixels of 15 in 8 bits, a zero byte that is skipped, then 100
+        // pixels of 5 in 16 bits
+        let run = (5u16 << 12) | (100 << 2) | 0b10;
+        let mut rle = vec![0xf7, 0x00];
+        rle.extend_from_slice(&run.to_le_bytes());
+
+        let bitmap = decode_logo_bitmap(&rle);
+        assert_eq!((bitmap.width, bitmap.height), (LOGO_WIDTH, LOGO_HEIGHT));
+
+        let pixels: Vec<&[u8]> = bitmap.rgba.chunks_exact(4).collect();
+        assert!(pixels[..3].iter().all(|p| *p == [255, 255, 255, 255]));
+        assert!(pixels[3..103].iter().all(|p| *p == [85, 85, 85, 255]));
+        assert!(pixels[103..].iter().all(|p| *p == [0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn logo_bitmap_runs_stop_at_the_last_pixel() {
+        // two runs of 1023 pixels, more than the logo has
+        let bitmap = decode_logo_bitmap(&[0xfe, 0xff, 0xfe, 0xff]);
+        assert!(
+            bitmap
+                .rgba
+                .chunks_exact(4)
+                .all(|p| p == [255, 255, 255, 255])
+        );
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use byteorder::{BE, ReadBytesExt};
+
+use num_enum::TryFromPrimitive;
+
+use anyhow::{Error, bail};
+
+/// Entry ID of the title's own image and name
+pub const XDBF_ID_TITLE: u64 = 0x8000;
+
+// metadata entry IDs are four-character codes
+const XDBF_ID_ACHIEVEMENTS: u64 = u32::from_be_bytes(*b"XACH") as u64;
+const XDBF_ID_DEFAULT_LANGUAGE: u64 = u32::from_be_bytes(*b"XSTC") as u64;
+
+const HEADER_SIZE: u64 = 24;
+const ENTRY_SIZE: u64 = 18;
+const FREE_ENTRY_SIZE: u64 = 8;
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TryFromPrimitive)]
+pub enum XdbfSection {
+    Metadata = 1,
+    Image = 2,
+    StringTable = 3,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, TryFromPrimitive)]
+pub enum XdbfLanguage {
+    English = 1,
+    Japanese = 2,
+    German = 3,
+    French = 4,
+    Spanish = 5,
+    Italian = 6,
+    Korean = 7,
+    TraditionalChinese = 8,
+    Portuguese = 9,
+    SimplifiedChinese = 10,
+    Polish = 11,
+    Russian = 12,
+}
+
+/// Strings for one language, by string ID.
+#[derive(Clone, Debug, Default)]
+pub struct XdbfStringTable {
+    strings: HashMap<u16, String>,
+}
+
+/// Names and descriptions are string IDs, and `image_id` is an image entry ID.
+#[derive(Clone, Debug)]
+pub struct XdbfAchievement {
+    pub id: u16,
+    pub label_id: u16,
+    pub description_id: u16,
+    pub unachieved_id: u16,
+    pub image_id: u32,
+    pub gamerscore: u16,
+    pub flags: u32,
+}
+
+#[derive(Clone, Debug)]
+struct XdbfEntry {
+    section: XdbfSection,
+    id: u64,
+    offset: usize,
+    size: usize,
+}
+
+/// An XDBF ("SPA") file, as embedded into an XEX as a resource named after the
+/// title ID.
+///
+/// Based on https://free60.org/System-Software/Formats/XDBF/
+#[derive(Clone, Debug)]
+pub struct Xdbf {
+    entries: Vec<XdbfEntry>,
+    data: Vec<u8>,
+}
+
+impl Xdbf {
+    pub fn parse(data: Vec<u8>) -> Result<Xdbf, Error> {
+        let mut reader = &data[..];
+
+        let mut magic_bytes = [0u8; 4];
+        reader.read_exact(&mut magic_bytes)?;
+        if &magic_bytes != b"XDBF" {
+            bail!("missing 'XDBF' magic bytes");
+        }
+
+        let _version = reader.read_u32::<BE>()?;
+        let entry_count = reader.read_u32::<BE>()? as u64;
+        let entries_used = reader.read_u32::<BE>()? as u64;
+        let free_count = reader.read_u32::<BE>()? as u64;
+        let _free_used = reader.read_u32::<BE>()?;
+
+        if entries_used > entry_count {
+            bail!("XDBF header uses {entries_used} out of {entry_count} entries");
+        }
+
+        // sizes are 32-bit, so this can not overflow
+        let data_offset = HEADER_SIZE + entry_count * ENTRY_SIZE + free_count * FREE_ENTRY_SIZE;
+        if data_offset > data.len() as u64 {
+            bail!("XDBF entry table is out of bounds");
+        }
+
+        let mut entries = Vec::with_capacity(entries_used as usize);
+
+        for _ in 0..entries_used {
+            let section = reader.read_u16::<BE>()?;
+            let id = reader.read_u64::<BE>()?;
+            let offset = data_offset + reader.read_u32::<BE>()? as u64;
+            let size = reader.read_u32::<BE>()? as u64;
+
+            if offset + size > data.len() as u64 {
+                bail!("XDBF entry {section}/{id:X} is out of bounds");
+            }
+
+            // unknown sections are skipped, not rejected
+            let Ok(section) = XdbfSection::try_from(section) else {
+                continue;
+            };
+
+            entries.push(XdbfEntry {
+                section,
+                id,
+                offset: offset as usize,
+                size: size as usize,
+            });
+        }
+
+        Ok(Xdbf { entries, data })
+    }
+
+    pub fn get_entry(&self, section: XdbfSection, id: u64) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|e| e.section == section && e.id == id)
+            .map(|e| &self.data[e.offset..e.offset + e.size])
+    }
+
+    /// The title's dashboard tile, a 64x64 PNG.
+    pub fn title_image(&self) -> Option<&[u8]> {
+        self.get_entry(XdbfSection::Image, XDBF_ID_TITLE)
+    }
+
+    /// All images (PNG), by entry ID: the title image, achievement images, etc.
+    pub fn images(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        self.entries
+            .iter()
+            .filter(|e| e.section == XdbfSection::Image)
+            .map(|e| (e.id, &self.data[e.offset..e.offset + e.size]))
+    }
+
+    /// Languages that have a string table, in file order.
+    pub fn languages(&self) -> impl Iterator<Item = XdbfLanguage> {
+        self.entries
+            .iter()
+            .filter(|e| e.section == XdbfSection::StringTable)
+            .filter_map(|e| u32::try_from(e.id).ok())
+            .filter_map(|id| XdbfLanguage::try_from(id).ok())
+    }
+
+    /// The language to use when the console's language has no string table.
+    pub fn default_language(&self) -> Result<XdbfLanguage, Error> {
+        let Some(mut data) = self.get_entry(XdbfSection::Metadata, XDBF_ID_DEFAULT_LANGUAGE) else {
+            return Ok(XdbfLanguage::English);
+        };
+
+        read_metadata_header(&mut data, b"XSTC")?;
+        let language = data.read_u32::<BE>()?;
+
+        match XdbfLanguage::try_from(language) {
+            Ok(language) => Ok(language),
+            Err(_) => bail!("unknown XDBF default language {language}"),
+        }
+    }
+
+    pub fn string_table(&self, language: XdbfLanguage) -> Result<Option<XdbfStringTable>, Error> {
+        let Some(mut data) = self.get_entry(XdbfSection::StringTable, language as u64) else {
+            return Ok(None);
+        };
+
+        read_metadata_header(&mut data, b"XSTR")?;
+        let count = data.read_u16::<BE>()?;
+
+        let mut strings = HashMap::with_capacity(count as usize);
+
+        for _ in 0..count {
+            let id = data.read_u16::<BE>()?;
+            let len = data.read_u16::<BE>()? as usize;
+
+            if len > data.len() {
+                bail!("XDBF string {id:04X} is out of bounds");
+            }
+
+            let (string, rest) = data.split_at(len);
+            strings.insert(id, String::from_utf8_lossy(string).into_owned());
+            data = rest;
+        }
+
+        Ok(Some(XdbfStringTable { strings }))
+    }
+
+    /// The title name in the given language, or in the default language if
+    /// there is no such translation.
+    pub fn title_name(&self, language: XdbfLanguage) -> Result<Option<String>, Error> {
+        for language in [language, self.default_language()?] {
+            let name = self
+                .string_table(language)?
+                .and_then(|table| table.get(XDBF_ID_TITLE as u16).map(str::to_owned));
+
+            if name.is_some() {
+                return Ok(name);
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn achievements(&self) -> Result<Vec<XdbfAchievement>, Error> {
+        let Some(mut data) = self.get_entry(XdbfSection::Metadata, XDBF_ID_ACHIEVEMENTS) else {
+            return Ok(Vec::new());
+        };
+
+        read_metadata_header(&mut data, b"XACH")?;
+        let count = data.read_u16::<BE>()?;
+
+        let mut achievements = Vec::with_capacity(count as usize);
+
+        for _ in 0..count {
+            let id = data.read_u16::<BE>()?;
+            let label_id = data.read_u16::<BE>()?;
+            let description_id = data.read_u16::<BE>()?;
+            let unachieved_id = data.read_u16::<BE>()?;
+            let image_id = data.read_u32::<BE>()?;
+            let gamerscore = data.read_u16::<BE>()?;
+            let _ = data.read_u16::<BE>()?;
+            let flags = data.read_u32::<BE>()?;
+
+            // unknown
+            let mut reserved = [0u8; 16];
+            data.read_exact(&mut reserved)?;
+
+            achievements.push(XdbfAchievement {
+                id,
+                label_id,
+                description_id,
+                unachieved_id,
+                image_id,
+                gamerscore,
+                flags,
+            });
+        }
+
+        Ok(achievements)
+    }
+}
+
+impl XdbfStringTable {
+    pub fn get(&self, id: u16) -> Option<&str> {
+        self.strings.get(&id).map(String::as_str)
+    }
+}
+
+/// Checks the magic bytes of a metadata or string table entry, and skips its
+/// version and size.
+fn read_metadata_header(data: &mut &[u8], magic: &[u8; 4]) -> Result<(), Error> {
+    let mut magic_bytes = [0u8; 4];
+    data.read_exact(&mut magic_bytes)?;
+
+    if &magic_bytes != magic {
+        bail!(
+            "missing '{}' magic bytes in XDBF entry",
+            String::from_utf8_lossy(magic)
+        );
+    }
+
+    let _version = data.read_u32::<BE>()?;
+    let _size = data.read_u32::<BE>()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::executable::xbe::{XbeCertificate, XbeMediaTypes, XbeRegion};
+    use crate::executable::{TitleExecutionInfo, TitleInfo};
+    use crate::god::ContentType;
+
+    /// A metadata or string table entry, after its magic bytes, version and
+    /// size.
+    fn metadata(magic: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = magic.to_vec();
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&(12 + body.len() as u32).to_be_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn string_table(strings: &[(u16, &str)]) -> Vec<u8> {
+        let mut body = (strings.len() as u16).to_be_bytes().to_vec();
+        for (id, string) in strings {
+            body.extend_from_slice(&id.to_be_bytes());
+            body.extend_from_slice(&(string.len() as u16).to_be_bytes());
+            body.extend_from_slice(string.as_bytes());
+        }
+        metadata(b"XSTR", &body)
+    }
+
+    fn default_language(language: XdbfLanguage) -> Vec<u8> {
+        metadata(b"XSTC", &(language as u32).to_be_bytes())
+    }
+
+    fn xdbf(entries: &[(XdbfSection, u64, Vec<u8>)]) -> Xdbf {
+        let mut file = b"XDBF".to_vec();
+        for value in [0x10000, entries.len() as u32, entries.len() as u32, 0, 0] {
+            file.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let mut offset = 0u32;
+        for (section, id, data) in entries {
+            file.extend_from_slice(&(*section as u16).to_be_bytes());
+            file.extend_from_slice(&id.to_be_bytes());
+            file.extend_from_slice(&offset.to_be_bytes());
+            file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            offset += data.len() as u32;
+        }
+
+        for (_, _, data) in entries {
+            file.extend_from_slice(data);
+        }
+
+        Xdbf::parse(file).unwrap()
+    }
+
+    const TITLE: u16 = XDBF_ID_TITLE as u16;
+
+    /// German by default, with an English table that has no title name.
+    fn german_xdbf() -> Xdbf {
+        xdbf(&[
+            (
+                XdbfSection::Metadata,
+                XDBF_ID_DEFAULT_LANGUAGE,
+                default_language(XdbfLanguage::German),
+            ),
+            (
+                XdbfSection::StringTable,
+                XdbfLanguage::English as u64,
+                string_table(&[(1, "Achievement")]),
+            ),
+            (
+                XdbfSection::StringTable,
+                XdbfLanguage::German as u64,
+                string_table(&[(1, "Erfolg"), (TITLE, "Der Titel")]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn title_name_falls_back_to_default_language() {
+        let xdbf = german_xdbf();
+        assert_eq!(xdbf.default_language().unwrap(), XdbfLanguage::German);
+        assert_eq!(
+            xdbf.languages().collect::<Vec<_>>(),
+            [XdbfLanguage::English, XdbfLanguage::German]
+        );
+
+        let english = xdbf.string_table(XdbfLanguage::English).unwrap().unwrap();
+        assert_eq!(english.get(1), Some("Achievement"));
+        assert_eq!(english.get(TITLE), None);
+
+        for language in [
+            XdbfLanguage::English,
+            XdbfLanguage::French,
+            XdbfLanguage::German,
+        ] {
+            let name = xdbf.title_name(language).unwrap();
+            assert_eq!(name.as_deref(), Some("Der Titel"), "{language:?}");
+        }
+    }
+
+    #[test]
+    fn default_language_is_english_without_xstc() {
+        let xdbf = xdbf(&[(
+            XdbfSection::StringTable,
+            XdbfLanguage::English as u64,
+            string_table(&[(TITLE, "The Title")]),
+        )]);
+        assert_eq!(xdbf.default_language().unwrap(), XdbfLanguage::English);
+        assert_eq!(
+            xdbf.title_name(XdbfLanguage::Japanese).unwrap().as_deref(),
+            Some("The Title")
+        );
+    }
+
+    fn title_info(title_id: u32, xdbf: Option<Xdbf>, certificate_name: &str) -> TitleInfo {
+        TitleInfo {
+            content_type: ContentType::GamesOnDemand,
+            execution_info: TitleExecutionInfo {
+                media_id: 0,
+                version: 0,
+                base_version: 0,
+                title_id,
+                platform: 0,
+                executable_type: 0,
+                disc_number: 1,
+                disc_count: 1,
+            },
+            game_icon: None,
+            xdbf,
+            xbe_certificate: Some(XbeCertificate {
+                time_date: 0,
+                title_id,
+                title_name: certificate_name.to_owned(),
+                alternate_title_ids: Vec::new(),
+                allowed_media: XbeMediaTypes::empty(),
+                game_region: XbeRegion::empty(),
+                game_ratings: 0,
+                disk_number: 0,
+                version: 0,
+                lan_key: [0; 16],
+                signature_key: [0; 16],
+            }),
+        }
+    }
+
+    #[test]
+    fn game_title_fallback_order() {
+        // the game list comes first, then the XDBF, then the certificate
+        let info = title_info(0x4d530004, Some(german_xdbf()), "Certificate");
+        assert_eq!(info.game_title().as_deref(), Some("Halo"));
+
+        let info = title_info(0x54455354, Some(german_xdbf()), "Certificate");
+        assert_eq!(info.game_title().as_deref(), Some("Der Titel"));
+
+        let info = title_info(0x54455354, None, "Certificate");
+        assert_eq!(info.game_title().as_deref(), Some("Certificate"));
+
+        let info = title_info(0x54455354, None, "");
+        assert_eq!(info.game_title(), None);
+    }
+
+    #[test]
+    fn entry_out_of_bounds_is_an_error() {
+        let mut file = b"XDBF".to_vec();
+        for value in [0x10000u32, 1, 1, 0, 0] {
+            file.extend_from_slice(&value.to_be_bytes());
+        }
+        file.extend_from_slice(&(XdbfSection::Image as u16).to_be_bytes());
+        file.extend_from_slice(&XDBF_ID_TITLE.to_be_bytes());
+        file.extend_from_slice(&0u32.to_be_bytes());
+        file.extend_from_slice(&4u32.to_be_bytes());
+
+        assert!(Xdbf::parse(file).is_err());
+    }
+}
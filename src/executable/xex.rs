@@ -1,18 +1,39 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::fmt;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 
-use byteorder::{BE, ReadBytesExt};
+use byteorder::{BE, ByteOrder, ReadBytesExt};
+
+use aes::Aes128;
+use aes::cipher::{BlockDecrypt, KeyInit, generic_array::GenericArray};
+
+use sha1::{Digest, Sha1};
 
 use bitflags::bitflags;
-use num_enum::TryFromPrimitive;
+use num_enum::{FromPrimitive, TryFromPrimitive};
+
+use crate::Iso2GodError;
+use crate::executable::{TitleExecutionInfo, lzx};
+use anyhow::{Context, Error, bail};
+
+const XEX_RETAIL_KEY: [u8; 16] = [
+    0x20, 0xb1, 0x85, 0xa5, 0x9d, 0x28, 0xfd, 0xc3, 0x40, 0x58, 0x3f, 0xbb, 0x08, 0x96, 0xbf, 0x91,
+];
+const XEX_DEVKIT_KEY: [u8; 16] = [0; 16];
 
-use crate::executable::TitleExecutionInfo;
-use anyhow::{Error, bail};
+/// More than the whole memory of the console
+const MAX_BASEFILE_SIZE: usize = 0x2000_0000;
+
+// sanity limits, real XEXs are well below them
+const MAX_HEADER_FIELD_COUNT: u32 = 0x0100;
+const MAX_HEADER_FIELD_SIZE: u32 = 0x0010_0000;
 
 #[derive(Clone, Debug)]
 pub struct XexHeader {
     pub module_flags: XexModuleFlags,
     pub code_offset: u32,
     pub certificate_offset: u32,
+    /// Only needed to read the basefile, so a malformed one is left out
+    pub security_info: Option<XexSecurityInfo>,
     pub fields: XexHeaderFields,
 }
 
@@ -31,12 +52,241 @@ bitflags! {
     }
 }
 
+bitflags! {
+    // based on https://free60.org/System-Software/Formats/XEX/#system-flags
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct XexSystemFlags: u32 {
+        const NO_FORCED_REBOOT = 0x00000001;
+        const FOREGROUND_TASKS = 0x00000002;
+        const NO_ODD_MAPPING = 0x00000004;
+        const HANDLE_MCE_INPUT = 0x00000008;
+        const RESTRICTED_HUD_FEATURES = 0x00000010;
+        const HANDLE_GAMEPAD_DISCONNECT = 0x00000020;
+        const INSECURE_SOCKETS = 0x00000040;
+        const XBOX1_INTEROPERABILITY = 0x00000080;
+        const DASH_CONTEXT = 0x00000100;
+        const USES_GAME_VOICE_CHANNEL = 0x00000200;
+        const PAL50_INCOMPATIBLE = 0x00000400;
+        const INSECURE_UTILITY_DRIVE = 0x00000800;
+        const XAM_HOOKS = 0x00001000;
+        const ACCESS_PII = 0x00002000;
+        const CROSS_PLATFORM_SYSTEM_LINK = 0x00004000;
+        const MULTIDISC_SWAP = 0x00008000;
+        const MULTIDISC_INSECURE_MEDIA = 0x00010000;
+        const AP25_MEDIA = 0x00020000;
+        const NO_CONFIRM_EXIT = 0x00040000;
+        const ALLOW_BACKGROUND_DOWNLOAD = 0x00080000;
+        const CREATE_PERSISTABLE_RAMDRIVE = 0x00100000;
+        const INHERIT_PERSISTENT_RAMDRIVE = 0x00200000;
+        const ALLOW_HUD_VIBRATION = 0x00400000;
+        const ACCESS_UTILITY_PARTITIONS = 0x00800000;
+        const IPTV_INPUT_SUPPORTED = 0x01000000;
+        const PREFER_BIG_BUTTON_INPUT = 0x02000000;
+        const ALLOW_EXTENDED_SYSTEM_RESERVATION = 0x04000000;
+        const MULTIDISC_CROSS_TITLE = 0x08000000;
+        const INSTALL_INCOMPATIBLE = 0x10000000;
+        const ALLOW_AVATAR_GET_METADATA_BY_XUID = 0x20000000;
+        const ALLOW_CONTROLLER_SWAPPING = 0x40000000;
+        const DASH_EXTENSIBILITY_MODULE = 0x80000000;
+    }
+}
+
+bitflags! {
+    // based on https://free60.org/System-Software/Formats/XEX/#image-flags
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct XexImageFlags: u32 {
+        const MANUFACTURING_UTILITY = 0x00000002;
+        const MANUFACTURING_SUPPORT_TOOLS = 0x00000004;
+        const XGD2_MEDIA_ONLY = 0x00000008;
+        const CARDEA_KEY = 0x00000100;
+        const XEIKA_KEY = 0x00000200;
+        const USERMODE_TITLE = 0x00000400;
+        const USERMODE_SYSTEM = 0x00000800;
+        const ORANGE0 = 0x00001000;
+        const ORANGE1 = 0x00002000;
+        const ORANGE2 = 0x00004000;
+        const IPTV_SIGNUP_APPLICATION = 0x00010000;
+        const IPTV_TITLE_APPLICATION = 0x00020000;
+        const KEYVAULT_PRIVILEGES_REQUIRED = 0x04000000;
+        const ONLINE_ACTIVATION_REQUIRED = 0x08000000;
+        const PAGE_SIZE_4KB = 0x10000000;
+        const REGION_FREE = 0x20000000;
+        const REVOCATION_CHECK_OPTIONAL = 0x40000000;
+        const REVOCATION_CHECK_REQUIRED = 0x80000000;
+    }
+}
+
+bitflags! {
+    // based on https://free60.org/System-Software/Formats/XEX/#allowed-media-types
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct XexMediaTypes: u32 {
+        const HARD_DISK = 0x00000001;
+        const DVD_X2 = 0x00000002;
+        const DVD_CD = 0x00000004;
+        const DVD_5 = 0x00000008;
+        const DVD_9 = 0x00000010;
+        const SYSTEM_FLASH = 0x00000020;
+        const MEMORY_UNIT = 0x00000080;
+        const USB_MASS_STORAGE_DEVICE = 0x00000100;
+        const NETWORK = 0x00000200;
+        const DIRECT_FROM_MEMORY = 0x00000400;
+        const RAM_DRIVE = 0x00000800;
+        const SVOD = 0x00001000;
+        const INSECURE_PACKAGE = 0x01000000;
+        const SAVEGAME_PACKAGE = 0x02000000;
+        const LOCALLY_SIGNED_PACKAGE = 0x04000000;
+        const LIVE_SIGNED_PACKAGE = 0x08000000;
+        const XBOX_PACKAGE = 0x10000000;
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct XexRegion: u32 {
+        const NTSC_U = 0x000000ff;
+        const NTSC_J_JAPAN = 0x00000100;
+        const NTSC_J_CHINA = 0x00000200;
+        const NTSC_J = 0x0000ff00;
+        const PAL_AUSTRALIA_NZ = 0x00010000;
+        const PAL = 0x00ff0000;
+        const OTHER = 0xff000000;
+    }
+}
+
+/// The security info ("certificate") block that `certificate_offset` points to.
+#[derive(Clone, Debug)]
+pub struct XexSecurityInfo {
+    pub header_size: u32,
+    pub image_size: u32,
+    pub image_flags: XexImageFlags,
+    pub load_address: u32,
+    pub import_table_count: u32,
+    /// Basefile key, itself encrypted with the retail or devkit key
+    pub aes_key: [u8; 16],
+    pub region: XexRegion,
+    pub allowed_media_types: XexMediaTypes,
+    pub page_descriptor_count: u32,
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct XexHeaderFields {
     pub execution_info: Option<TitleExecutionInfo>,
+    pub base_file_format: Option<XexBaseFileFormat>,
+    pub resource_info: Vec<XexResource>,
+    pub game_ratings: Option<XexGameRatings>,
+    pub system_flags: Option<XexSystemFlags>,
+    pub multidisc_media_ids: Vec<u32>,
+    pub alternate_title_ids: Vec<u32>,
+    pub original_pe_name: Option<String>,
+    pub import_libraries: Vec<XexImportLibrary>,
+    pub static_libraries: Vec<XexStaticLibrary>,
     // other fields will be added if and when necessary
 }
 
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromPrimitive)]
+pub enum XexEncryption {
+    None = 0,
+    Normal = 1,
+    #[num_enum(catch_all)]
+    Unknown(u16),
+}
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromPrimitive)]
+pub enum XexCompression {
+    None = 0,
+    Basic = 1,
+    Normal = 2,
+    Delta = 3,
+    #[num_enum(catch_all)]
+    Unknown(u16),
+}
+
+/// How the basefile (the actual PE image) is stored after the XEX headers.
+#[derive(Clone, Debug)]
+pub struct XexBaseFileFormat {
+    pub encryption: XexEncryption,
+    pub compression: XexCompression,
+    /// Stored data followed by zeroes, for basic compression
+    pub basic_blocks: Vec<XexBasicBlock>,
+    /// LZX parameters, for normal compression
+    pub normal_compression: Option<XexNormalCompression>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct XexBasicBlock {
+    pub data_size: u32,
+    pub zero_size: u32,
+}
+
+/// Normally compressed data is a chain of blocks, each starting with the size
+/// and SHA-1 of the next one; the first one is described here.
+#[derive(Clone, Debug)]
+pub struct XexNormalCompression {
+    pub window_size: u32,
+    pub first_block_size: u32,
+    pub first_block_hash: [u8; 20],
+}
+
+/// The decrypted and decompressed basefile (PE image) of an XEX.
+///
+/// Positions are relative to the start of the image; use
+/// [`XexBasefile::seek_to_address`] to go to a virtual address instead.
+pub struct XexBasefile {
+    image: Cursor<Vec<u8>>,
+    load_address: u32,
+}
+
+/// A resource embedded in the image, such as the XDBF title data.
+#[derive(Clone, Debug)]
+pub struct XexResource {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+}
+
+/// Age ratings by rating board; `None` means the title is not rated.
+#[derive(Clone, Debug)]
+pub struct XexGameRatings {
+    pub esrb: Option<u8>,
+    pub pegi: Option<u8>,
+    pub pegi_fi: Option<u8>,
+    pub pegi_pt: Option<u8>,
+    pub bbfc: Option<u8>,
+    pub cero: Option<u8>,
+    pub usk: Option<u8>,
+    pub oflc_au: Option<u8>,
+    pub oflc_nz: Option<u8>,
+    pub kmrb: Option<u8>,
+    pub brazil: Option<u8>,
+    pub fpb: Option<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct XexVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub build: u16,
+    pub qfe: u16,
+}
+
+/// A system library that the image imports from, like `xam.xex` or `xboxkrnl.exe`.
+#[derive(Clone, Debug)]
+pub struct XexImportLibrary {
+    pub name: String,
+    pub version: XexVersion,
+    pub min_version: XexVersion,
+    pub import_count: u16,
+}
+
+/// A library that was statically linked into the image, like `XAPILIB`.
+#[derive(Clone, Debug)]
+pub struct XexStaticLibrary {
+    pub name: String,
+    pub version: XexVersion,
+}
+
 // based on https://free60.org/System-Software/Formats/XEX/#header-ids
 #[repr(u32)]
 #[derive(Clone, Debug, PartialEq, Eq, TryFromPrimitive)]
@@ -82,13 +332,19 @@ impl XexHeader {
     }
 
     fn check_magic_bytes<R: Read + Seek>(mut reader: R) -> Result<(), Error> {
+        let offset = reader.stream_position()?;
+
         let mut buf = [0_u8; 4];
         reader.read_exact(&mut buf)?;
 
         reader.seek(SeekFrom::Current(-4))?;
 
         if buf != "XEX2".as_bytes() {
-            bail!("missing 'XEX2' magic bytes in XEX header");
+            return Err(Iso2GodError::BadMagic {
+                offset,
+                expected: "'XEX2'",
+            }
+            .into());
         }
 
         Ok(())
@@ -110,27 +366,697 @@ impl XexHeader {
 
         let mut fields: XexHeaderFields = Default::default();
         let field_count = reader.read_u32::<BE>()?;
+        if field_count > MAX_HEADER_FIELD_COUNT {
+            bail!("too many fields in XEX header: {field_count}");
+        }
 
         for _ in 0..field_count {
             let key = reader.read_u32::<BE>()?;
             let value = reader.read_u32::<BE>()?;
 
-            let key = XexHeaderFieldId::try_from(key).ok();
-            type Key = XexHeaderFieldId;
-
-            if let Some(Key::ExecutionId) = key {
-                let offset = reader.stream_position()?;
-                reader.seek(SeekFrom::Start(header_offset + (value as u64)))?;
-                fields.execution_info = Some(TitleExecutionInfo::from_xex(&mut reader)?);
-                reader.seek(SeekFrom::Start(offset))?;
+            let Ok(key) = XexHeaderFieldId::try_from(key) else {
+                continue;
             };
+
+            // only the execution ID is needed to convert the image; any other
+            // field that is malformed is left out
+            let required = key == XexHeaderFieldId::ExecutionId;
+
+            let offset = reader.stream_position()?;
+            let result = fields.read_field(&mut reader, key, header_offset, value);
+            if required {
+                result?;
+            }
+            reader.seek(SeekFrom::Start(offset))?;
         }
 
+        let security_info = reader
+            .seek(SeekFrom::Start(header_offset + (certificate_offset as u64)))
+            .map_err(Error::from)
+            .and_then(|_| XexSecurityInfo::read(&mut reader))
+            .ok();
+
         Ok(XexHeader {
             module_flags,
             code_offset,
             certificate_offset,
+            security_info,
             fields,
         })
     }
 }
+
+impl XexHeader {
+    /// Reads a resource out of the basefile, such as the XDBF title data that
+    /// is named after the title ID. `reader` has to be at the start of the XEX,
+    /// same as for [`XexHeader::read`].
+    pub fn read_resource<R: Read + Seek>(
+        &self,
+        mut reader: R,
+        name: &str,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let Some(resource) = self.fields.resource_info.iter().find(|r| r.name == name) else {
+            return Ok(None);
+        };
+
+        let mut basefile = self.read_basefile(&mut reader)?;
+        basefile.seek_to_address(resource.address)?;
+
+        let mut data = Vec::new();
+        basefile.take(resource.size as u64).read_to_end(&mut data)?;
+
+        if data.len() < resource.size as usize {
+            bail!("XEX resource {name} is truncated");
+        }
+
+        Ok(Some(data))
+    }
+
+    /// Decrypts and decompresses the basefile. `reader` has to be at the start
+    /// of the XEX, same as for [`XexHeader::read`].
+    ///
+    /// Both the retail and the devkit keys are tried; the right one is found
+    /// by the block hashes for normal compression, or by the PE magic bytes
+    /// otherwise.
+    pub fn read_basefile<R: Read + Seek>(&self, mut reader: R) -> Result<XexBasefile, Error> {
+        let Some(security_info) = &self.security_info else {
+            bail!("XEX security info is missing or malformed");
+        };
+
+        let header_offset = reader.stream_position()?;
+        let data_offset = header_offset + (self.code_offset as u64);
+
+        let image_size = security_info.image_size as usize;
+        if image_size > MAX_BASEFILE_SIZE {
+            bail!("XEX basefile is too large: {image_size} bytes");
+        }
+
+        let encryption = self
+            .fields
+            .base_file_format
+            .as_ref()
+            .map_or(XexEncryption::None, |f| f.encryption);
+
+        let keys: &[Option<&[u8; 16]>] = match encryption {
+            XexEncryption::None => &[None],
+            XexEncryption::Normal => &[Some(&XEX_RETAIL_KEY), Some(&XEX_DEVKIT_KEY)],
+            XexEncryption::Unknown(encryption) => {
+                bail!("unknown XEX basefile encryption type {encryption}")
+            }
+        };
+
+        let mut last_error = None;
+
+        for key in keys {
+            reader.seek(SeekFrom::Start(data_offset))?;
+
+            let data: Box<dyn Read + '_> = match key {
+                None => Box::new(&mut reader),
+                Some(key) => Box::new(AesCbcReader::new(&mut reader, key, &security_info.aes_key)),
+            };
+
+            match self.decode_basefile(data, image_size) {
+                Ok(image) if image.starts_with(b"MZ") => {
+                    return Ok(XexBasefile {
+                        image: Cursor::new(image),
+                        load_address: security_info.load_address,
+                    });
+                }
+                Ok(_) => last_error = Some(Error::msg("XEX basefile is not a PE image")),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error
+            .unwrap()
+            .context("error decoding XEX basefile (wrong key, or corrupted)"))
+    }
+
+    fn decode_basefile<R: Read>(&self, mut data: R, image_size: usize) -> Result<Vec<u8>, Error> {
+        let Some(format) = &self.fields.base_file_format else {
+            return read_exact_vec(data, image_size);
+        };
+
+        match format.compression {
+            XexCompression::None => read_exact_vec(data, image_size),
+            XexCompression::Basic => {
+                // checked before anything is read, so that a corrupt header
+                // can not make us fill hundreds of megabytes with zeroes first
+                let blocks_size: u64 = format
+                    .basic_blocks
+                    .iter()
+                    .map(|block| (block.data_size as u64) + (block.zero_size as u64))
+                    .sum();
+                if blocks_size > image_size as u64 {
+                    bail!("XEX basefile blocks are larger than the image");
+                }
+
+                // grown as blocks are read, since the sizes are untrusted
+                let mut image = Vec::new();
+
+                for block in &format.basic_blocks {
+                    image.extend(read_exact_vec(&mut data, block.data_size as usize)?);
+
+                    // give up on the wrong key before padding with zeroes
+                    if image.len() >= 2 && !image.starts_with(b"MZ") {
+                        bail!("XEX basefile is not a PE image");
+                    }
+
+                    image.resize(image.len() + block.zero_size as usize, 0);
+                }
+
+                image.resize(image_size, 0);
+                Ok(image)
+            }
+            XexCompression::Normal => {
+                let normal = format
+                    .normal_compression
+                    .as_ref()
+                    .context("missing normal compression info in XEX header")?;
+
+                let mut compressed = Vec::new();
+
+                let mut block_size = normal.first_block_size as usize;
+                let mut block_hash = normal.first_block_hash;
+
+                while block_size != 0 {
+                    if block_size < 24 || block_size > MAX_BASEFILE_SIZE - compressed.len() {
+                        bail!("invalid XEX compressed block size {block_size}");
+                    }
+
+                    let block = read_exact_vec(&mut data, block_size)?;
+
+                    let digest: [u8; 20] = Sha1::digest(&block).into();
+                    if digest != block_hash {
+                        bail!("XEX compressed block hash mismatch");
+                    }
+
+                    let mut block = &block[..];
+                    block_size = block.read_u32::<BE>()? as usize;
+                    block.read_exact(&mut block_hash)?;
+
+                    // LZX data is split into chunks of at most 32 KiB
+                    loop {
+                        let chunk_size = block.read_u16::<BE>()? as usize;
+                        if chunk_size == 0 {
+                            break;
+                        }
+                        if chunk_size > block.len() {
+                            bail!("XEX compressed chunk runs past the end of its block");
+                        }
+
+                        let (chunk, rest) = block.split_at(chunk_size);
+                        compressed.extend_from_slice(chunk);
+                        block = rest;
+                    }
+                }
+
+                lzx::decompress(&compressed, normal.window_size, image_size)
+            }
+            XexCompression::Delta => bail!("delta patch XEX basefiles are not supported"),
+            XexCompression::Unknown(compression) => {
+                bail!("unknown XEX basefile compression type {compression}")
+            }
+        }
+    }
+}
+
+impl XexBasefile {
+    pub fn load_address(&self) -> u32 {
+        self.load_address
+    }
+
+    pub fn seek_to_address(&mut self, address: u32) -> Result<u64, Error> {
+        let Some(offset) = address.checked_sub(self.load_address) else {
+            bail!("address {address:08X} is below the XEX load address");
+        };
+
+        Ok(self.image.seek(SeekFrom::Start(offset as u64))?)
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.image.into_inner()
+    }
+}
+
+impl Read for XexBasefile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.image.read(buf)
+    }
+}
+
+impl Seek for XexBasefile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.image.seek(pos)
+    }
+}
+
+/// AES-128-CBC with a zero IV, using the file key from the security info,
+/// itself decrypted with the retail or devkit key.
+struct AesCbcReader<R: Read> {
+    inner: R,
+    cipher: Aes128,
+    iv: [u8; 16],
+    block: [u8; 16],
+    block_position: usize,
+}
+
+impl<R: Read> AesCbcReader<R> {
+    fn new(inner: R, key: &[u8; 16], encrypted_file_key: &[u8; 16]) -> AesCbcReader<R> {
+        let mut file_key = GenericArray::clone_from_slice(encrypted_file_key);
+        Aes128::new(GenericArray::from_slice(key)).decrypt_block(&mut file_key);
+
+        AesCbcReader {
+            inner,
+            cipher: Aes128::new(&file_key),
+            iv: [0; 16],
+            block: [0; 16],
+            block_position: 16,
+        }
+    }
+}
+
+impl<R: Read> Read for AesCbcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.block_position == 16 {
+            let mut ciphertext = [0u8; 16];
+            match self.inner.read_exact(&mut ciphertext) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+
+            let mut block = GenericArray::from(ciphertext);
+            self.cipher.decrypt_block(&mut block);
+
+            for (b, iv) in block.iter_mut().zip(self.iv) {
+                *b ^= iv;
+            }
+
+            self.block = block.into();
+            self.iv = ciphertext;
+            self.block_position = 0;
+        }
+
+        let len = buf.len().min(16 - self.block_position);
+        buf[..len].copy_from_slice(&self.block[self.block_position..self.block_position + len]);
+        self.block_position += len;
+        Ok(len)
+    }
+}
+
+fn read_exact_vec<R: Read>(reader: R, size: usize) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    reader.take(size as u64).read_to_end(&mut data)?;
+
+    if data.len() < size {
+        bail!("XEX basefile is truncated");
+    }
+
+    Ok(data)
+}
+
+impl XexSecurityInfo {
+    fn read<R: Read + Seek>(mut reader: R) -> Result<XexSecurityInfo, Error> {
+        let header_size = reader.read_u32::<BE>()?;
+        let image_size = reader.read_u32::<BE>()?;
+
+        // RSA signature, and the size of the signed data
+        reader.seek(SeekFrom::Current(0x100 + 4))?;
+
+        let image_flags = XexImageFlags::from_bits_truncate(reader.read_u32::<BE>()?);
+        let load_address = reader.read_u32::<BE>()?;
+
+        // section digest
+        reader.seek(SeekFrom::Current(0x14))?;
+        let import_table_count = reader.read_u32::<BE>()?;
+
+        // import table digest, XGD2 media ID
+        reader.seek(SeekFrom::Current(0x14 + 0x10))?;
+        let mut aes_key = [0u8; 16];
+        reader.read_exact(&mut aes_key)?;
+
+        // export table address, header digest
+        reader.seek(SeekFrom::Current(4 + 0x14))?;
+        let region = XexRegion::from_bits_truncate(reader.read_u32::<BE>()?);
+        let allowed_media_types = XexMediaTypes::from_bits_truncate(reader.read_u32::<BE>()?);
+        let page_descriptor_count = reader.read_u32::<BE>()?;
+
+        Ok(XexSecurityInfo {
+            header_size,
+            image_size,
+            image_flags,
+            load_address,
+            import_table_count,
+            aes_key,
+            region,
+            allowed_media_types,
+            page_descriptor_count,
+        })
+    }
+}
+
+impl XexHeaderFields {
+    fn read_field<R: Read + Seek>(
+        &mut self,
+        mut reader: R,
+        key: XexHeaderFieldId,
+        header_offset: u64,
+        value: u32,
+    ) -> Result<(), Error> {
+        type Key = XexHeaderFieldId;
+
+        // fields with a size of 0 or 1 (in the low byte of the key) store their
+        // value inline; all others point to their data, relative to the header
+        if key == Key::SystemFlags {
+            self.system_flags = Some(XexSystemFlags::from_bits_truncate(value));
+            return Ok(());
+        }
+
+        reader.seek(SeekFrom::Start(header_offset + (value as u64)))?;
+
+        match key {
+            Key::ExecutionId => {
+                self.execution_info = Some(TitleExecutionInfo::from_xex(&mut reader)?);
+            }
+            Key::BaseFileFormat => {
+                let mut data = &read_sized_field(&mut reader)?[..];
+                // only needed to read resources, so unknown types are kept
+                // for read_basefile to fail on
+                let encryption = XexEncryption::from(data.read_u16::<BE>()?);
+                let compression = XexCompression::from(data.read_u16::<BE>()?);
+
+                let mut basic_blocks = Vec::new();
+                let mut normal_compression = None;
+
+                match compression {
+                    XexCompression::Basic => {
+                        for block in data.chunks_exact(8) {
+                            basic_blocks.push(XexBasicBlock {
+                                data_size: BE::read_u32(&block[0..]),
+                                zero_size: BE::read_u32(&block[4..]),
+                            });
+                        }
+                    }
+                    XexCompression::Normal => {
+                        let window_size = data.read_u32::<BE>()?;
+                        let first_block_size = data.read_u32::<BE>()?;
+                        let mut first_block_hash = [0u8; 20];
+                        data.read_exact(&mut first_block_hash)?;
+
+                        normal_compression = Some(XexNormalCompression {
+                            window_size,
+                            first_block_size,
+                            first_block_hash,
+                        });
+                    }
+                    XexCompression::None | XexCompression::Delta | XexCompression::Unknown(_) => {}
+                }
+
+                self.base_file_format = Some(XexBaseFileFormat {
+                    encryption,
+                    compression,
+                    basic_blocks,
+                    normal_compression,
+                });
+            }
+            Key::ResourceInfo => {
+                let data = read_sized_field(&mut reader)?;
+                self.resource_info = data.chunks_exact(16).map(XexResource::parse).collect();
+            }
+            Key::GameRatings => {
+                let mut data = [0u8; 0x40];
+                reader.read_exact(&mut data)?;
+                self.game_ratings = Some(XexGameRatings::parse(&data));
+            }
+            Key::MultidiscMediaIds => {
+                let data = read_sized_field(&mut reader)?;
+                self.multidisc_media_ids = data.chunks_exact(4).map(BE::read_u32).collect();
+            }
+            Key::AlternateTitleIds => {
+                let data = read_sized_field(&mut reader)?;
+                self.alternate_title_ids = data.chunks_exact(4).map(BE::read_u32).collect();
+            }
+            Key::OriginalPeName => {
+                let data = read_sized_field(&mut reader)?;
+                self.original_pe_name = Some(read_c_string(&data));
+            }
+            Key::ImportLibraries => {
+                let data = read_sized_field(&mut reader)?;
+                self.import_libraries = XexImportLibrary::parse_all(&data)?;
+            }
+            Key::StaticLibraries => {
+                let data = read_sized_field(&mut reader)?;
+                self.static_libraries =
+                    data.chunks_exact(16).map(XexStaticLibrary::parse).collect();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl XexResource {
+    fn parse(data: &[u8]) -> XexResource {
+        XexResource {
+            name: read_c_string(&data[0..8]),
+            address: BE::read_u32(&data[8..]),
+            size: BE::read_u32(&data[12..]),
+        }
+    }
+}
+
+impl XexGameRatings {
+    fn parse(data: &[u8; 0x40]) -> XexGameRatings {
+        let rating = |index: usize| Some(data[index]).filter(|&r| r != 0xff);
+
+        XexGameRatings {
+            esrb: rating(0),
+            pegi: rating(1),
+            pegi_fi: rating(2),
+            pegi_pt: rating(3),
+            bbfc: rating(4),
+            cero: rating(5),
+            usk: rating(6),
+            oflc_au: rating(7),
+            oflc_nz: rating(8),
+            kmrb: rating(9),
+            brazil: rating(10),
+            fpb: rating(11),
+        }
+    }
+}
+
+impl From<u32> for XexVersion {
+    /// Unpacks the 4.4.16.8-bit version format used by import libraries
+    fn from(value: u32) -> XexVersion {
+        XexVersion {
+            major: (value >> 28) as u16,
+            minor: ((value >> 24) & 0xf) as u16,
+            build: ((value >> 8) & 0xffff) as u16,
+            qfe: (value & 0xff) as u16,
+        }
+    }
+}
+
+impl fmt::Display for XexVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.major, self.minor, self.build, self.qfe
+        )
+    }
+}
+
+impl XexImportLibrary {
+    fn parse_all(data: &[u8]) -> Result<Vec<XexImportLibrary>, Error> {
+        let mut reader = data;
+
+        let string_table_size = reader.read_u32::<BE>()? as usize;
+        let _string_count = reader.read_u32::<BE>()?;
+
+        if string_table_size > reader.len() {
+            bail!("import library name table is out of bounds");
+        }
+
+        // names are NUL-terminated, and padded to 4 bytes with more NULs
+        let (string_table, mut reader) = reader.split_at(string_table_size);
+        let names: Vec<String> = string_table
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect();
+
+        let mut libraries = Vec::new();
+
+        while !reader.is_empty() {
+            let mut library = reader;
+
+            let size = library.read_u32::<BE>()? as usize;
+            if size < 0x28 || size > reader.len() {
+                bail!("invalid import library size {size}");
+            }
+
+            // digest of the next import library, and library ID
+            library = &library[0x14 + 4..];
+
+            let version = XexVersion::from(library.read_u32::<BE>()?);
+            let min_version = XexVersion::from(library.read_u32::<BE>()?);
+            let name_index = library.read_u16::<BE>()?;
+            let import_count = library.read_u16::<BE>()?;
+
+            libraries.push(XexImportLibrary {
+                name: names.get(name_index as usize).cloned().unwrap_or_default(),
+                version,
+                min_version,
+                import_count,
+            });
+
+            reader = &reader[size..];
+        }
+
+        Ok(libraries)
+    }
+}
+
+impl XexStaticLibrary {
+    fn parse(data: &[u8]) -> XexStaticLibrary {
+        XexStaticLibrary {
+            name: read_c_string(&data[0..8]),
+            version: XexVersion {
+                major: BE::read_u16(&data[8..]),
+                minor: BE::read_u16(&data[10..]),
+                build: BE::read_u16(&data[12..]),
+                // the high byte is the approval type
+                qfe: data[15] as u16,
+            },
+        }
+    }
+}
+
+/// Reads a variable-sized field, which starts with its own size (including
+/// the size itself).
+fn read_sized_field<R: Read>(mut reader: R) -> Result<Vec<u8>, Error> {
+    let size = reader.read_u32::<BE>()?;
+    if !(4..=MAX_HEADER_FIELD_SIZE).contains(&size) {
+        bail!("invalid XEX header field size {size}");
+    }
+
+    let mut data = Vec::new();
+    reader.take((size - 4) as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn read_c_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aes::cipher::BlockEncrypt;
+
+    /// An XEX header with the given fields, each with its data at its own
+    /// offset, and an empty security info.
+    fn header_with_fields(fields: &[(XexHeaderFieldId, &[u8])]) -> Vec<u8> {
+        let mut header = vec![0u8; 0x100 * (fields.len() + 3)];
+        let certificate_offset = header.len() - 0x200;
+
+        header[0..4].copy_from_slice(b"XEX2");
+        BE::write_u32(&mut header[0x10..], certificate_offset as u32);
+        BE::write_u32(&mut header[0x14..], fields.len() as u32);
+
+        for (i, (key, data)) in fields.iter().enumerate() {
+            let offset = 0x100 * (i + 1);
+            BE::write_u32(&mut header[0x18 + i * 8..], key.clone() as u32);
+            BE::write_u32(&mut header[0x1c + i * 8..], offset as u32);
+            header[offset..offset + data.len()].copy_from_slice(data);
+        }
+
+        header
+    }
+
+    const EXECUTION_ID: &[u8] = &[
+        0x12, 0x34, 0x56, 0x78, 0, 0, 0, 0, 0, 0, 0, 0, 0x41, 0x42, 0x43, 0x44, 0, 0, 1, 1,
+    ];
+
+    #[test]
+    fn malformed_optional_field_is_left_out() {
+        let header = header_with_fields(&[
+            (XexHeaderFieldId::ExecutionId, EXECUTION_ID),
+            (XexHeaderFieldId::ImportLibraries, &[0xff; 12]),
+        ]);
+
+        let header = XexHeader::read(Cursor::new(header)).unwrap();
+        let execution_info = header.fields.execution_info.unwrap();
+        assert_eq!(execution_info.title_id, 0x41424344);
+        assert!(header.fields.import_libraries.is_empty());
+    }
+
+    #[test]
+    fn malformed_execution_id_is_an_error() {
+        // the field runs past the end of the header
+        let mut header = header_with_fields(&[(XexHeaderFieldId::ExecutionId, EXECUTION_ID)]);
+        let len = header.len() as u32;
+        BE::write_u32(&mut header[0x1c..], len - 4);
+
+        assert!(XexHeader::read(Cursor::new(header)).is_err());
+    }
+
+    #[test]
+    fn bad_certificate_offset_fails_only_when_read() {
+        let mut header = header_with_fields(&[(XexHeaderFieldId::ExecutionId, EXECUTION_ID)]);
+        BE::write_u32(&mut header[0x10..], 0xffff_0000);
+
+        let xex = XexHeader::read(Cursor::new(&header)).unwrap();
+        assert!(xex.fields.execution_info.is_some());
+        assert!(xex.security_info.is_none());
+
+        assert!(xex.read_basefile(Cursor::new(&header)).is_err());
+    }
+
+    #[test]
+    fn unknown_basefile_format_fails_only_when_read() {
+        let header = header_with_fields(&[
+            (XexHeaderFieldId::ExecutionId, EXECUTION_ID),
+            (XexHeaderFieldId::BaseFileFormat, &[0, 0, 0, 8, 0, 7, 0, 1]),
+        ]);
+
+        let xex = XexHeader::read(Cursor::new(&header)).unwrap();
+        let format = xex.fields.base_file_format.as_ref().unwrap();
+        assert_eq!(format.encryption, XexEncryption::Unknown(7));
+
+        assert!(xex.read_basefile(Cursor::new(&header)).is_err());
+    }
+
+    #[test]
+    fn aes_cbc_reader_decrypts_fips_197_vector() {
+        // FIPS-197 appendix C.1; with a zero IV, the first CBC block is the
+        // same as ECB
+        let file_key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let plaintext: [u8; 16] = core::array::from_fn(|i| (i as u8) * 0x11);
+        let ciphertext = [
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+            0xc5, 0x5a,
+        ];
+
+        let mut encrypted_file_key = GenericArray::from(file_key);
+        Aes128::new(GenericArray::from_slice(&XEX_RETAIL_KEY))
+            .encrypt_block(&mut encrypted_file_key);
+
+        let mut reader =
+            AesCbcReader::new(&ciphertext[..], &XEX_RETAIL_KEY, &encrypted_file_key.into());
+
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+}
@@ -0,0 +1,437 @@
+use std::io::Read;
+
+use byteorder::{ByteOrder, LE, ReadBytesExt};
+
+use anyhow::{Error, bail};
+
+use super::bitmap::Bitmap;
+
+// larger textures are not expected in an XBE, and would only waste memory
+const MAX_TEXTURE_SIZE: usize = 1024;
+
+const FORMAT_R5G6B5: u32 = 0x05;
+const FORMAT_A8R8G8B8: u32 = 0x06;
+const FORMAT_X8R8G8B8: u32 = 0x07;
+const FORMAT_DXT1: u32 = 0x0c;
+const FORMAT_DXT3: u32 = 0x0e;
+const FORMAT_DXT5: u32 = 0x0f;
+const FORMAT_LIN_R5G6B5: u32 = 0x11;
+const FORMAT_LIN_A8R8G8B8: u32 = 0x12;
+const FORMAT_LIN_X8R8G8B8: u32 = 0x1e;
+
+/// Decodes the first mip level of the texture in an XPR0 resource bundle, as
+/// found in the `$$XTIMAGE` and `$$XSIMAGE` sections of an XBE.
+///
+/// Based on https://xboxdevwiki.net/Xbe#Title_and_Save_images
+pub fn decode(data: &[u8]) -> Result<Bitmap, Error> {
+    let mut reader = data;
+
+    let mut magic_bytes = [0u8; 4];
+    reader.read_exact(&mut magic_bytes)?;
+    if &magic_bytes != b"XPR0" {
+        bail!("missing 'XPR0' magic bytes");
+    }
+
+    let _total_size = reader.read_u32::<LE>()?;
+    let header_size = reader.read_u32::<LE>()? as usize;
+
+    // D3DTexture resource header
+    let _common = reader.read_u32::<LE>()?;
+    let data_offset = reader.read_u32::<LE>()? as usize;
+    let _lock = reader.read_u32::<LE>()?;
+    let format = reader.read_u32::<LE>()?;
+    let size = reader.read_u32::<LE>()?;
+
+    let texture_format = (format >> 8) & 0xff;
+
+    let (width, height, pitch) = if size != 0 {
+        let width = (size & 0xfff) as usize + 1;
+        let height = ((size >> 12) & 0xfff) as usize + 1;
+        let pitch = (((size >> 24) & 0xff) as usize + 1) * 64;
+        (width, height, Some(pitch))
+    } else {
+        let width = 1usize << ((format >> 20) & 0xf);
+        let height = 1usize << ((format >> 24) & 0xf);
+        (width, height, None)
+    };
+
+    if width > MAX_TEXTURE_SIZE || height > MAX_TEXTURE_SIZE {
+        bail!("XPR texture is too large: {width}x{height}");
+    }
+
+    let Some(texture) = header_size
+        .checked_add(data_offset)
+        .and_then(|offset| data.get(offset..))
+    else {
+        bail!("XPR texture data is out of bounds");
+    };
+
+    match texture_format {
+        FORMAT_DXT1 | FORMAT_DXT3 | FORMAT_DXT5 => {
+            decode_dxt(texture, texture_format, width, height)
+        }
+        FORMAT_A8R8G8B8 | FORMAT_X8R8G8B8 | FORMAT_R5G6B5 => {
+            decode_uncompressed(texture, texture_format, width, height, |x, y| {
+                swizzled_offset(x, y, width, height)
+            })
+        }
+        FORMAT_LIN_A8R8G8B8 | FORMAT_LIN_X8R8G8B8 | FORMAT_LIN_R5G6B5 => {
+            let bytes_per_pixel = if texture_format == FORMAT_LIN_R5G6B5 {
+                2
+            } else {
+                4
+            };
+            let pitch = pitch.unwrap_or(width * bytes_per_pixel);
+            decode_uncompressed(texture, texture_format, width, height, |x, y| {
+                (y * pitch) / bytes_per_pixel + x
+            })
+        }
+        _ => bail!("unsupported XPR texture format {texture_format:02X}"),
+    }
+}
+
+fn decode_uncompressed(
+    texture: &[u8],
+    format: u32,
+    width: usize,
+    height: usize,
+    pixel_index: impl Fn(usize, usize) -> usize,
+) -> Result<Bitmap, Error> {
+    let has_alpha = matches!(format, FORMAT_A8R8G8B8 | FORMAT_LIN_A8R8G8B8);
+    let bytes_per_pixel = match format {
+        FORMAT_R5G6B5 | FORMAT_LIN_R5G6B5 => 2,
+        _ => 4,
+    };
+
+    let mut bitmap = Bitmap::new(width, height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let offset = pixel_index(x, y) * bytes_per_pixel;
+            let Some(src) = texture.get(offset..offset + bytes_per_pixel) else {
+                bail!("XPR texture data is truncated");
+            };
+
+            let rgba = if bytes_per_pixel == 2 {
+                let [r, g, b] = rgb565(LE::read_u16(src));
+                [r, g, b, 0xff]
+            } else {
+                // stored as B, G, R, A
+                [
+                    src[2],
+                    src[1],
+                    src[0],
+                    if has_alpha { src[3] } else { 0xff },
+                ]
+            };
+
+            bitmap.pixel_mut(x, y).copy_from_slice(&rgba);
+        }
+    }
+
+    Ok(bitmap)
+}
+
+fn decode_dxt(texture: &[u8], format: u32, width: usize, height: usize) -> Result<Bitmap, Error> {
+    let block_size = if format == FORMAT_DXT1 { 8 } else { 16 };
+    let blocks_wide = width.div_ceil(4);
+    let blocks_high = height.div_ceil(4);
+
+    if texture.len() < blocks_wide * blocks_high * block_size {
+        bail!("XPR texture data is truncated");
+    }
+
+    let mut bitmap = Bitmap::new(width, height);
+
+    for (i, block) in texture
+        .chunks_exact(block_size)
+        .take(blocks_wide * blocks_high)
+        .enumerate()
+    {
+        let (block_x, block_y) = ((i % blocks_wide) * 4, (i / blocks_wide) * 4);
+
+        let (alpha, colors) = match format {
+            FORMAT_DXT1 => (None, block),
+            FORMAT_DXT3 => (Some(dxt3_alpha(&block[..8])), &block[8..]),
+            _ => (Some(dxt5_alpha(&block[..8])), &block[8..]),
+        };
+
+        let palette = dxt_palette(colors, format == FORMAT_DXT1);
+        let indices = LE::read_u32(&colors[4..8]);
+
+        for p in 0..16 {
+            let (x, y) = (block_x + p % 4, block_y + p / 4);
+            if x >= width || y >= height {
+                continue;
+            }
+
+            let mut rgba = palette[((indices >> (p * 2)) & 0b11) as usize];
+            if let Some(alpha) = alpha {
+                rgba[3] = alpha[p];
+            }
+
+            bitmap.pixel_mut(x, y).copy_from_slice(&rgba);
+        }
+    }
+
+    Ok(bitmap)
+}
+
+fn dxt_palette(block: &[u8], dxt1: bool) -> [[u8; 4]; 4] {
+    let c0 = LE::read_u16(&block[0..2]);
+    let c1 = LE::read_u16(&block[2..4]);
+
+    let [r0, g0, b0] = rgb565(c0).map(u16::from);
+    let [r1, g1, b1] = rgb565(c1).map(u16::from);
+
+    let mix = |a: u16, b: u16, wa: u16, wb: u16| ((a * wa + b * wb) / (wa + wb)) as u8;
+
+    let first = [r0 as u8, g0 as u8, b0 as u8, 0xff];
+    let second = [r1 as u8, g1 as u8, b1 as u8, 0xff];
+
+    // DXT1 blocks with c0 <= c1 have a single mixed color, and transparency
+    if dxt1 && c0 <= c1 {
+        [
+            first,
+            second,
+            [
+                mix(r0, r1, 1, 1),
+                mix(g0, g1, 1, 1),
+                mix(b0, b1, 1, 1),
+                0xff,
+            ],
+            [0, 0, 0, 0],
+        ]
+    } else {
+        [
+            first,
+            second,
+            [
+                mix(r0, r1, 2, 1),
+                mix(g0, g1, 2, 1),
+                mix(b0, b1, 2, 1),
+                0xff,
+            ],
+            [
+                mix(r0, r1, 1, 2),
+                mix(g0, g1, 1, 2),
+                mix(b0, b1, 1, 2),
+                0xff,
+            ],
+        ]
+    }
+}
+
+fn dxt3_alpha(block: &[u8]) -> [u8; 16] {
+    let bits = LE::read_u64(block);
+    std::array::from_fn(|p| ((bits >> (p * 4)) & 0xf) as u8 * 17)
+}
+
+fn dxt5_alpha(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u16, block[1] as u16);
+    let bits = LE::read_u48(&block[2..8]);
+
+    let palette: [u8; 8] = if a0 > a1 {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            i => ((a0 * (8 - i as u16) + a1 * (i as u16 - 1)) / 7) as u8,
+        })
+    } else {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            6 => 0,
+            7 => 0xff,
+            i => ((a0 * (6 - i as u16) + a1 * (i as u16 - 1)) / 5) as u8,
+        })
+    };
+
+    std::array::from_fn(|p| palette[((bits >> (p * 3)) & 0b111) as usize])
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let r = ((color >> 11) & 0x1f) as u8;
+    let g = ((color >> 5) & 0x3f) as u8;
+    let b = (color & 0x1f) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+/// Index of a pixel in a swizzled (Morton order) texture, where the bits of
+/// `x` and `y` are interleaved for as long as both dimensions have some left.
+fn swizzled_offset(mut x: usize, mut y: usize, mut width: usize, mut height: usize) -> usize {
+    let mut offset = 0;
+    let mut shift = 0;
+
+    while width > 1 || height > 1 {
+        if width > 1 {
+            offset |= (x & 1) << shift;
+            shift += 1;
+            x >>= 1;
+            width >>= 1;
+        }
+
+        if height > 1 {
+            offset |= (y & 1) << shift;
+            shift += 1;
+            y >>= 1;
+            height >>= 1;
+        }
+    }
+
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An XPR0 bundle with a single swizzled texture of 2^`log2_width` x
+    /// 2^`log2_height` pixels.
+    fn xpr(texture_format: u32, log2_width: u32, log2_height: u32, texture: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"XPR0");
+        for value in [
+            0x20 + texture.len() as u32,
+            0x20, // header size
+            0,    // common
+            0,    // data offset
+            0,    // lock
+            (texture_format << 8) | (log2_width << 20) | (log2_height << 24),
+            0, // size
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(texture);
+        data
+    }
+
+    /// Color endpoints, then pixel `p` using palette entry `p % 4`.
+    fn dxt_color_block(c0: u16, c1: u16) -> Vec<u8> {
+        let indices = (0..16).fold(0u32, |indices, p| indices | ((p % 4) << (p * 2)));
+        let mut block = Vec::new();
+        block.extend_from_slice(&c0.to_le_bytes());
+        block.extend_from_slice(&c1.to_le_bytes());
+        block.extend_from_slice(&indices.to_le_bytes());
+        block
+    }
+
+    fn pixels(bitmap: &Bitmap) -> Vec<[u8; 4]> {
+        bitmap
+            .rgba
+            .chunks_exact(4)
+            .map(|p| p.try_into().unwrap())
+            .collect()
+    }
+
+    const RED: u16 = 0xf800;
+    const BLUE: u16 = 0x001f;
+
+    #[test]
+    fn dxt1_block_is_decoded() {
+        let bitmap = decode(&xpr(FORMAT_DXT1, 2, 2, &dxt_color_block(RED, BLUE))).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (4, 4));
+
+        let palette = [
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+            [170, 0, 85, 255],
+            [85, 0, 170, 255],
+        ];
+        for (p, pixel) in pixels(&bitmap).into_iter().enumerate() {
+            assert_eq!(pixel, palette[p % 4], "pixel {p}");
+        }
+    }
+
+    #[test]
+    fn dxt1_block_with_transparency_is_decoded() {
+        let bitmap = decode(&xpr(FORMAT_DXT1, 2, 2, &dxt_color_block(BLUE, RED))).unwrap();
+
+        let palette = [
+            [0, 0, 255, 255],
+            [255, 0, 0, 255],
+            [127, 0, 127, 255],
+            [0, 0, 0, 0],
+        ];
+        for (p, pixel) in pixels(&bitmap).into_iter().enumerate() {
+            assert_eq!(pixel, palette[p % 4], "pixel {p}");
+        }
+    }
+
+    #[test]
+    fn dxt3_block_is_decoded() {
+        // pixel `p` has an alpha of `p`, in 4 bits
+        let alpha = (0..16).fold(0u64, |alpha, p| alpha | (p << (p * 4)));
+        let mut block = alpha.to_le_bytes().to_vec();
+        block.extend(dxt_color_block(BLUE, RED));
+
+        let bitmap = decode(&xpr(FORMAT_DXT3, 2, 2, &block)).unwrap();
+
+        // no transparent color outside of DXT1
+        let colors = [[0, 0, 255], [255, 0, 0], [85, 0, 170], [170, 0, 85]];
+        for (p, pixel) in pixels(&bitmap).into_iter().enumerate() {
+            assert_eq!(pixel[..3], colors[p % 4], "pixel {p}");
+            assert_eq!(pixel[3], p as u8 * 17, "pixel {p}");
+        }
+    }
+
+    #[test]
+    fn dxt5_block_is_decoded() {
+        // pixel `p` uses alpha palette entry `p % 8`
+        let indices = (0..16).fold(0u64, |indices, p| indices | ((p % 8) << (p * 3)));
+        let mut block = vec![255, 0];
+        block.extend_from_slice(&indices.to_le_bytes()[..6]);
+        block.extend(dxt_color_block(RED, BLUE));
+
+        let bitmap = decode(&xpr(FORMAT_DXT5, 2, 2, &block)).unwrap();
+
+        let alpha_palette = [255, 0, 218, 182, 145, 109, 72, 36];
+        for (p, pixel) in pixels(&bitmap).into_iter().enumerate() {
+            assert_eq!(pixel[3], alpha_palette[p % 8], "pixel {p}");
+        }
+    }
+
+    #[test]
+    fn swizzled_offsets_interleave_coordinates() {
+        assert_eq!(swizzled_offset(1, 0, 4, 4), 1);
+        assert_eq!(swizzled_offset(0, 1, 4, 4), 2);
+        assert_eq!(swizzled_offset(2, 1, 4, 4), 6);
+        assert_eq!(swizzled_offset(3, 3, 4, 4), 15);
+
+        // once `height` runs out, the rest of `x` follows
+        assert_eq!(swizzled_offset(2, 1, 4, 2), 6);
+        assert_eq!(swizzled_offset(3, 1, 4, 2), 7);
+    }
+
+    #[test]
+    fn swizzled_texture_is_decoded() {
+        // the blue channel of each pixel is its index in the texture
+        let texture: Vec<u8> = (0..64).flat_map(|i| [i, 0, 0, 0xff]).collect();
+        let bitmap = decode(&xpr(FORMAT_A8R8G8B8, 3, 3, &texture)).unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+             0,  1,  4,  5, 16, 17, 20, 21,
+             2,  3,  6,  7, 18, 19, 22, 23,
+             8,  9, 12, 13, 24, 25, 28, 29,
+            10, 11, 14, 15, 26, 27, 30, 31,
+            32, 33, 36, 37, 48, 49, 52, 53,
+            34, 35, 38, 39, 50, 51, 54, 55,
+            40, 41, 44, 45, 56, 57, 60, 61,
+            42, 43, 46, 47, 58, 59, 62, 63,
+        ];
+        let blue: Vec<u8> = pixels(&bitmap).iter().map(|pixel| pixel[2]).collect();
+        assert_eq!(blue, expected);
+    }
+
+    #[test]
+    fn truncated_texture_is_an_error() {
+        let block = dxt_color_block(RED, BLUE);
+        assert!(decode(&xpr(FORMAT_DXT1, 2, 2, &block[..7])).is_err());
+        assert!(decode(&xpr(FORMAT_A8R8G8B8, 3, 3, &[0; 255])).is_err());
+    }
+}
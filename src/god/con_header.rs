@@ -1,21 +1,192 @@
-use byteorder::{BE, ByteOrder, LE};
+use std::io::{Read, Seek, SeekFrom};
+
+use byteorder::{BE, ByteOrder, LE, ReadBytesExt};
+
+use num_enum::TryFromPrimitive;
 
 use sha1::{Digest, Sha1};
 
+use anyhow::{Error, bail};
+
+use crate::Iso2GodError;
 use crate::executable::TitleExecutionInfo;
 
 const EMPTY_LIVE: &[u8] = include_bytes!("empty_live.bin");
 
+pub const MAX_ICON_SIZE: usize = 0x4000;
+
+/// Maximum length of a game title in UTF-16 units; each title slot is 0x80
+/// bytes including the terminating null.
+pub const MAX_GAME_TITLE_LEN: usize = 0x3f;
+
+const PNG_MAGIC_BYTES: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+const CON_MAGIC_BYTES: [&[u8; 4]; 3] = [b"LIVE", b"PIRS", b"CON "];
+
+/// Checks that an icon can be passed to [`ConHeaderBuilder::with_game_icon`].
+pub fn check_game_icon(png_bytes: &[u8]) -> Result<(), Error> {
+    if !png_bytes.starts_with(PNG_MAGIC_BYTES) {
+        bail!("game icon is not a PNG image");
+    }
+
+    if png_bytes.len() > MAX_ICON_SIZE {
+        bail!(
+            "game icon is too large: {} bytes (at most {} allowed)",
+            png_bytes.len(),
+            MAX_ICON_SIZE
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that a title fits [`ConHeaderBuilder::with_game_title`] without
+/// being truncated.
+pub fn check_game_title(game_title: &str) -> Result<(), Error> {
+    let len = game_title.encode_utf16().count();
+
+    if len > MAX_GAME_TITLE_LEN {
+        bail!(
+            "game title is too long: {} characters (at most {} allowed)",
+            len,
+            MAX_GAME_TITLE_LEN
+        );
+    }
+
+    Ok(())
+}
+
 pub struct ConHeaderBuilder {
     buffer: Vec<u8>,
 }
 
-#[derive(Clone, Copy)]
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TryFromPrimitive)]
 pub enum ContentType {
     GamesOnDemand = 0x7000,
     XboxOriginal = 0x5000,
 }
 
+#[derive(Clone, Debug)]
+pub struct ConHeader {
+    pub content_type: ContentType,
+    pub execution_info: TitleExecutionInfo,
+    pub blocks_allocated: u32,
+    pub blocks_not_allocated: u16,
+    pub mht_hash: [u8; 20],
+    pub part_count: u32,
+    pub parts_total_size: u64,
+    pub game_title: String,
+    pub game_icon: Vec<u8>,
+    pub title_icon: Vec<u8>,
+}
+
+impl ConHeader {
+    /// Reads the header from the start of `reader`, which has to be the CON
+    /// header file itself; all fields are at fixed offsets from its start.
+    pub fn read<R: Read + Seek>(mut reader: R) -> Result<ConHeader, Error> {
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut magic_bytes = [0u8; 4];
+        reader.read_exact(&mut magic_bytes)?;
+
+        if !CON_MAGIC_BYTES.contains(&&magic_bytes) {
+            return Err(Iso2GodError::BadMagic {
+                offset: 0,
+                expected: "'LIVE', 'PIRS' or 'CON '",
+            }
+            .into());
+        }
+
+        reader.seek(SeekFrom::Start(0x0344))?;
+        let content_type = reader.read_u32::<BE>()?;
+        let Ok(content_type) = ContentType::try_from(content_type) else {
+            bail!("unsupported content type {content_type:08X} in CON header");
+        };
+
+        reader.seek(SeekFrom::Start(0x0354))?;
+        let execution_info = TitleExecutionInfo::from_xex(&mut reader)?;
+
+        reader.seek(SeekFrom::Start(0x037d))?;
+        let mut mht_hash = [0u8; 20];
+        reader.read_exact(&mut mht_hash)?;
+
+        reader.seek(SeekFrom::Start(0x0392))?;
+        let blocks_allocated = reader.read_u24::<BE>()?;
+        let blocks_not_allocated = reader.read_u16::<BE>()?;
+
+        reader.seek(SeekFrom::Start(0x03a0))?;
+        let part_count = reader.read_u32::<LE>()?; // sic!
+        let parts_total_size = (reader.read_u32::<BE>()? as u64) * 0x0100;
+
+        reader.seek(SeekFrom::Start(0x0411))?;
+        let game_title = Self::read_utf16_be(&mut reader, 0x80)?;
+
+        reader.seek(SeekFrom::Start(0x1712))?;
+        let game_icon_size = reader.read_u32::<BE>()?;
+        let title_icon_size = reader.read_u32::<BE>()?;
+
+        reader.seek(SeekFrom::Start(0x171a))?;
+        let game_icon = Self::read_icon(&mut reader, game_icon_size)?;
+
+        reader.seek(SeekFrom::Start(0x571a))?;
+        let title_icon = Self::read_icon(&mut reader, title_icon_size)?;
+
+        Ok(ConHeader {
+            content_type,
+            execution_info,
+            blocks_allocated,
+            blocks_not_allocated,
+            mht_hash,
+            part_count,
+            parts_total_size,
+            game_title,
+            game_icon,
+            title_icon,
+        })
+    }
+
+    /// Whether the file starts with the magic bytes of a CON header, as the
+    /// first file of a GOD package does. The position is left unchanged.
+    pub fn detect<R: Read + Seek>(mut reader: R) -> Result<bool, Error> {
+        let offset = reader.stream_position()?;
+
+        let mut magic_bytes = [0u8; 4];
+        let result = reader.read_exact(&mut magic_bytes);
+        reader.seek(SeekFrom::Start(offset))?;
+
+        match result {
+            Ok(()) => Ok(CON_MAGIC_BYTES.contains(&&magic_bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn read_utf16_be<R: Read>(mut reader: R, max_size: usize) -> Result<String, Error> {
+        let mut buf = vec![0u8; max_size];
+        reader.read_exact(&mut buf)?;
+
+        let units = buf
+            .chunks_exact(2)
+            .map(BE::read_u16)
+            .take_while(|&c| c != 0);
+
+        Ok(char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect())
+    }
+
+    fn read_icon<R: Read>(mut reader: R, size: u32) -> Result<Vec<u8>, Error> {
+        if size as usize > MAX_ICON_SIZE {
+            bail!("icon size {size} in CON header is too large");
+        }
+
+        let mut icon = vec![0u8; size as usize];
+        reader.read_exact(&mut icon)?;
+        Ok(icon)
+    }
+}
+
 impl Default for ConHeaderBuilder {
     fn default() -> Self {
         Self::new()
@@ -29,6 +200,19 @@ impl ConHeaderBuilder {
         }
     }
 
+    /// Starts from an existing CON header instead of the empty one.
+    pub fn from_bytes(buffer: Vec<u8>) -> Result<Self, Error> {
+        if buffer.len() != EMPTY_LIVE.len() {
+            bail!(
+                "unexpected CON header size {} (expected {})",
+                buffer.len(),
+                EMPTY_LIVE.len()
+            );
+        }
+
+        Ok(ConHeaderBuilder { buffer })
+    }
+
     fn write_u8(&mut self, offset: usize, value: u8) {
         self.buffer[offset] = value;
     }
@@ -87,18 +271,36 @@ impl ConHeaderBuilder {
         self
     }
 
-    pub fn with_game_icon(mut self, png_bytes: Option<&[u8]>) -> Self {
+    /// `None` clears the icon; anything else has to pass [`check_game_icon`].
+    pub fn with_game_icon(mut self, png_bytes: Option<&[u8]>) -> Result<Self, Error> {
+        if let Some(png_bytes) = png_bytes {
+            check_game_icon(png_bytes)?;
+        }
         let png_bytes = png_bytes.unwrap_or(&[]);
-        assert!(png_bytes.len() <= 0x0400);
 
         self.write_u32_be(0x1712, png_bytes.len() as u32);
         self.write_u32_be(0x1716, png_bytes.len() as u32);
+        self.write_bytes(0x171a, &[0; MAX_ICON_SIZE]);
+        self.write_bytes(0x571a, &[0; MAX_ICON_SIZE]);
         self.write_bytes(0x171a, png_bytes);
         self.write_bytes(0x571a, png_bytes);
-        self
+        Ok(self)
     }
 
+    /// Titles longer than [`MAX_GAME_TITLE_LEN`] UTF-16 units are truncated.
     pub fn with_game_title(mut self, game_title: &str) -> Self {
+        let mut len = 0;
+        let end = game_title
+            .char_indices()
+            .find(|(_, c)| {
+                len += c.len_utf16();
+                len > MAX_GAME_TITLE_LEN
+            })
+            .map_or(game_title.len(), |(i, _)| i);
+        let game_title = &game_title[..end];
+
+        self.write_bytes(0x0411, &[0; 0x80]);
+        self.write_bytes(0x1691, &[0; 0x80]);
         self.write_utf16_be(0x0411, game_title);
         self.write_utf16_be(0x1691, game_title);
         self
@@ -120,3 +322,59 @@ impl ConHeaderBuilder {
         self.buffer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn execution_info() -> TitleExecutionInfo {
+        TitleExecutionInfo {
+            media_id: 0x12345678,
+            version: 0,
+            base_version: 0,
+            title_id: 0x54455354,
+            platform: 0,
+            executable_type: 0,
+            disc_number: 1,
+            disc_count: 1,
+        }
+    }
+
+    #[test]
+    fn block_counts_are_read_back() {
+        let buffer = ConHeaderBuilder::new()
+            .with_execution_info(&execution_info())
+            .with_content_type(ContentType::GamesOnDemand)
+            .with_block_counts(0x0a1c4a, 0x1234)
+            .finalize();
+
+        let con_header = ConHeader::read(Cursor::new(buffer)).unwrap();
+        assert_eq!(con_header.blocks_allocated, 0x0a1c4a);
+        assert_eq!(con_header.blocks_not_allocated, 0x1234);
+    }
+
+    #[test]
+    fn long_game_title_is_truncated() {
+        let icon = b"\x89PNG\r\n\x1a\nicon";
+        let buffer = ConHeaderBuilder::new()
+            .with_execution_info(&execution_info())
+            .with_content_type(ContentType::GamesOnDemand)
+            .with_game_icon(Some(icon))
+            .unwrap()
+            .with_game_title(&"\u{1f3ae}".repeat(20_000))
+            .finalize();
+
+        let con_header = ConHeader::read(Cursor::new(buffer)).unwrap();
+        // a surrogate pair is not split at the end
+        assert_eq!(
+            con_header.game_title,
+            "\u{1f3ae}".repeat(MAX_GAME_TITLE_LEN / 2)
+        );
+        assert_eq!(con_header.game_icon, icon);
+
+        assert!(check_game_title(&"x".repeat(MAX_GAME_TITLE_LEN)).is_ok());
+        assert!(check_game_title(&"x".repeat(MAX_GAME_TITLE_LEN + 1)).is_err());
+    }
+}
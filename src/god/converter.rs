@@ -0,0 +1,454 @@
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{Context, Error, bail};
+
+use rayon::prelude::*;
+
+use crate::Iso2GodError;
+use crate::executable::TitleInfo;
+use crate::iso::{ImageHealth, ImageReader, IsoReader, RebuiltImage, ZeroPadReader};
+
+use super::*;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum TrimMode {
+    /// Trim unallocated space from the end
+    #[default]
+    FromEnd,
+
+    /// Trim nothing
+    None,
+
+    /// Rebuild the filesystem, packing all files together without gaps
+    FullRebuild,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConvertOptions {
+    pub trim_mode: TrimMode,
+
+    /// Used instead of the title from the built-in game list
+    pub game_title: Option<String>,
+
+    /// PNG used instead of the icon from the executable
+    pub game_icon: Option<Vec<u8>>,
+
+    /// Convert a truncated image anyway, as if it was padded with zeroes
+    pub force: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConvertStage {
+    RebuildingFilesystem,
+    ClearingDataDirectory,
+    WritingParts { part_count: u64 },
+    CalculatingMhtHashChain,
+    WritingConHeader,
+}
+
+/// Receives progress updates from [`Converter::convert`].
+///
+/// Part files are written in parallel, so `part_written` can be called from
+/// several threads at once.
+pub trait ConvertProgress: Sync {
+    fn stage(&self, _stage: ConvertStage) {}
+    fn part_written(&self, _parts_written: u64, _part_count: u64) {}
+}
+
+impl ConvertProgress for () {}
+
+#[derive(Clone, Debug)]
+pub struct ConvertResult {
+    pub title_id: u32,
+    pub content_type: ContentType,
+    pub game_title: Option<String>,
+    pub title_dir_path: PathBuf,
+    pub con_header_path: PathBuf,
+    pub data_dir_path: PathBuf,
+    pub data_size: u64,
+    pub block_count: u64,
+    pub part_count: u64,
+    pub parts_total_size: u64,
+}
+
+/// Converts an ISO image into a GOD package.
+///
+/// Every part file gets its own clone of the source image reader, so that parts
+/// can be written in parallel on the current rayon thread pool. Split and
+/// compressed images are read as plain ones, see [`ImageReader`].
+pub struct Converter {
+    source: ImageReader,
+    source_iso: IsoReader<ImageReader>,
+    title_info: TitleInfo,
+    health: ImageHealth,
+}
+
+impl Converter {
+    pub fn open(source_path: &Path) -> Result<Converter, Error> {
+        let source_iso_file =
+            ImageReader::open(source_path).context("error opening source ISO file")?;
+        let source = source_iso_file.try_clone()?;
+
+        let source_iso = IsoReader::read(source_iso_file).context("error reading source ISO")?;
+
+        let title_info =
+            TitleInfo::from_image(&source_iso).context("error reading image executable")?;
+
+        let health = ImageHealth::check(&source_iso);
+
+        Ok(Converter {
+            source,
+            source_iso,
+            title_info,
+            health,
+        })
+    }
+
+    pub fn source_iso(&self) -> &IsoReader<ImageReader> {
+        &self.source_iso
+    }
+
+    pub fn title_info(&self) -> &TitleInfo {
+        &self.title_info
+    }
+
+    pub fn health(&self) -> &ImageHealth {
+        &self.health
+    }
+
+    /// Size of the data volume that will be written with the given trim mode.
+    pub fn data_size(&self, trim_mode: TrimMode) -> Result<u64, Error> {
+        Ok(match trim_mode {
+            TrimMode::FromEnd => self.source_iso.get_max_used_prefix_size(),
+            TrimMode::None => self.health.volume_size.max(self.health.used_size),
+            TrimMode::FullRebuild => {
+                RebuiltImage::new(&self.source_iso)
+                    .context("error rebuilding filesystem")?
+                    .size
+            }
+        })
+    }
+
+    pub fn convert(
+        &self,
+        dest_dir: &Path,
+        options: &ConvertOptions,
+        progress: &dyn ConvertProgress,
+    ) -> Result<ConvertResult, Error> {
+        let exe_info = &self.title_info.execution_info;
+        let content_type = self.title_info.content_type;
+
+        // checked before any part is written, rather than at the end
+        if let Some(game_icon) = &options.game_icon {
+            check_game_icon(game_icon)?;
+        }
+
+        // the data of a truncated image is completed with zeroes
+        let root_offset = self.source_iso.volume_descriptor.root_offset;
+
+        if self.health.is_truncated() && !options.force {
+            return Err(Error::from(Iso2GodError::Truncated {
+                offset: root_offset + self.health.volume_size,
+                missing: self.health.missing_size(),
+            })
+            .context("source image is truncated"));
+        }
+        let padded_size = root_offset + self.health.volume_size.max(self.health.used_size);
+
+        let rebuilt_image = if options.trim_mode == TrimMode::FullRebuild {
+            progress.stage(ConvertStage::RebuildingFilesystem);
+            Some(RebuiltImage::new(&self.source_iso).context("error rebuilding filesystem")?)
+        } else {
+            None
+        };
+
+        let data_size = match &rebuilt_image {
+            Some(rebuilt_image) => rebuilt_image.size,
+            None => self.data_size(options.trim_mode)?,
+        };
+
+        let block_count = data_size.div_ceil(BLOCK_SIZE);
+        let part_count = block_count.div_ceil(BLOCKS_PER_PART);
+
+        if part_count == 0 {
+            bail!("nothing to convert: the game partition is empty");
+        }
+
+        let file_layout = FileLayout::new(dest_dir, exe_info, content_type);
+
+        progress.stage(ConvertStage::ClearingDataDirectory);
+
+        ensure_empty_dir(&file_layout.data_dir_path()).context("error clearing data directory")?;
+
+        progress.stage(ConvertStage::WritingParts { part_count });
+
+        let parts_written = AtomicU64::new(0);
+
+        (0..part_count).into_par_iter().try_for_each(|part_index| {
+            let mut source_iso_file = ZeroPadReader::new(self.source.try_clone()?, padded_size)?;
+
+            let part_file = file_layout.part_file_path(part_index);
+
+            let part_file = File::options()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&part_file)
+                .context("error creating part file")?;
+
+            if let Some(rebuilt_image) = &rebuilt_image {
+                write_part(rebuilt_image.reader(source_iso_file), part_index, part_file)
+            } else {
+                source_iso_file.seek(SeekFrom::Start(root_offset))?;
+                write_part(source_iso_file, part_index, part_file)
+            }
+            .context("error writing part file")?;
+
+            let cur = 1 + parts_written.fetch_add(1, Ordering::Relaxed);
+            progress.part_written(cur, part_count);
+
+            Ok::<_, Error>(())
+        })?;
+
+        progress.stage(ConvertStage::CalculatingMhtHashChain);
+
+        let mut mht =
+            read_part_mht(&file_layout, part_count - 1).context("error reading part file MHT")?;
+
+        for prev_part_index in (0..part_count - 1).rev() {
+            let mut prev_mht = read_part_mht(&file_layout, prev_part_index)
+                .context("error reading part file MHT")?;
+
+            prev_mht.add_hash(&mht.digest());
+
+            write_part_mht(&file_layout, prev_part_index, &prev_mht)
+                .context("error writing part file MHT")?;
+
+            mht = prev_mht;
+        }
+
+        let last_part_size = fs::metadata(file_layout.part_file_path(part_count - 1))
+            .map(|m| m.len())
+            .context("error reading part file")?;
+
+        let parts_total_size = last_part_size + (part_count - 1) * BLOCK_SIZE * 0xa290;
+
+        progress.stage(ConvertStage::WritingConHeader);
+
+        let mut con_header = ConHeaderBuilder::new()
+            .with_execution_info(exe_info)
+            .with_block_counts(block_count as u32, 0)
+            .with_data_parts_info(part_count as u32, parts_total_size)
+            .with_content_type(content_type)
+            .with_mht_hash(&mht.digest());
+
+        let game_title = options
+            .game_title
+            .clone()
+            .or_else(|| self.title_info.game_title());
+        if let Some(game_title) = &game_title {
+            con_header = con_header.with_game_title(game_title);
+        }
+
+        // an unusable icon from the executable is skipped, keeping the default one
+        let game_icon = options.game_icon.as_deref().or(self
+            .title_info
+            .game_icon
+            .as_deref()
+            .filter(|game_icon| check_game_icon(game_icon).is_ok()));
+        if game_icon.is_some() {
+            con_header = con_header.with_game_icon(game_icon)?;
+        }
+
+        let con_header = con_header.finalize();
+
+        let mut con_header_file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(file_layout.con_header_file_path())
+            .context("cannot open con header file")?;
+
+        con_header_file
+            .write_all(&con_header)
+            .context("error writing con header file")?;
+
+        Ok(ConvertResult {
+            title_id: exe_info.title_id,
+            content_type,
+            game_title,
+            title_dir_path: file_layout.title_dir_path(),
+            con_header_path: file_layout.con_header_file_path(),
+            data_dir_path: file_layout.data_dir_path(),
+            data_size,
+            block_count,
+            part_count,
+            parts_total_size,
+        })
+    }
+}
+
+fn ensure_empty_dir(path: &Path) -> Result<(), Error> {
+    if fs::exists(path)? {
+        fs::remove_dir_all(path)?;
+    };
+    fs::create_dir_all(path)?;
+    Ok(())
+}
+
+fn read_part_mht(file_layout: &FileLayout, part_index: u64) -> Result<HashList, Error> {
+    let part_file = file_layout.part_file_path(part_index);
+    let mut part_file = File::options().read(true).open(part_file)?;
+    HashList::read(&mut part_file)
+}
+
+fn write_part_mht(file_layout: &FileLayout, part_index: u64, mht: &HashList) -> Result<(), Error> {
+    let part_file = file_layout.part_file_path(part_index);
+    let mut part_file = File::options().write(true).open(part_file)?;
+    mht.write(&mut part_file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use byteorder::{ByteOrder, LE};
+    use tempfile::{TempDir, tempdir};
+
+    use crate::iso::{DirectoryImage, iso_type::IsoType};
+
+    /// An XBE with nothing but a certificate, for title ID 0x54455354.
+    fn xbe() -> Vec<u8> {
+        const BASE_ADDR: u32 = 0x0001_0000;
+
+        let mut xbe = vec![0u8; 0x1000];
+        xbe[0..4].copy_from_slice(b"XBEH");
+        LE::write_u32(&mut xbe[0x104..], BASE_ADDR);
+        LE::write_u32(&mut xbe[0x108..], 0x1000);
+        LE::write_u32(&mut xbe[0x118..], BASE_ADDR + 0x200);
+
+        LE::write_u32(&mut xbe[0x208..], 0x54455354);
+        for (i, c) in "Round Trip".encode_utf16().enumerate() {
+            LE::write_u16(&mut xbe[0x20c + i * 2..], c);
+        }
+
+        xbe
+    }
+
+    /// Writes an image with an XBE and some data, and returns its path.
+    fn source_image(dir: &TempDir) -> (PathBuf, Vec<u8>) {
+        let source_dir = dir.path().join("source");
+        fs::create_dir_all(source_dir.join("media")).unwrap();
+        fs::write(source_dir.join("default.xbe"), xbe()).unwrap();
+        for i in 0..8 {
+            let data: Vec<u8> = (0..0x9000 * i).map(|b| (b * i) as u8).collect();
+            fs::write(source_dir.join("media").join(format!("{i}.bin")), data).unwrap();
+        }
+
+        let mut image = Vec::new();
+        DirectoryImage::new(&source_dir)
+            .unwrap()
+            .write(&mut image)
+            .unwrap();
+
+        let image_path = dir.path().join("source.iso");
+        fs::write(&image_path, &image).unwrap();
+        (image_path, image)
+    }
+
+    fn convert(trim_mode: TrimMode) -> (TempDir, Vec<u8>, ConvertResult, Vec<u8>) {
+        let dir = tempdir().unwrap();
+        let (image_path, image) = source_image(&dir);
+
+        let converter = Converter::open(&image_path).unwrap();
+        let options = ConvertOptions {
+            trim_mode,
+            ..Default::default()
+        };
+        let dest_dir = dir.path().join("dest");
+        let result = converter.convert(&dest_dir, &options, &()).unwrap();
+
+        let exe_info = &converter.title_info().execution_info;
+        let file_layout = FileLayout::new(&dest_dir, exe_info, result.content_type);
+
+        let report = verify(&file_layout).unwrap();
+        assert!(report.is_ok(), "{:?}", report.corruptions);
+
+        let mut god_image = Vec::new();
+        god2iso(
+            &file_layout,
+            result.part_count,
+            IsoType::Xsf,
+            &mut god_image,
+        )
+        .unwrap();
+
+        (dir, image, result, god_image)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (_dir, image, result, god_image) = convert(TrimMode::None);
+
+        assert_eq!(result.content_type, ContentType::XboxOriginal);
+        assert_eq!(result.game_title.as_deref(), Some("Round Trip"));
+        assert_eq!(result.part_count, 1);
+        assert_eq!(god_image, image);
+
+        let mut god_reader = GodReader::open_con_header(&result.con_header_path).unwrap();
+        let mut god_data = Vec::new();
+        std::io::Read::read_to_end(&mut god_reader, &mut god_data).unwrap();
+        assert_eq!(god_data, image);
+    }
+
+    #[test]
+    fn part_files_are_counted_by_con_header() {
+        let (dir, _image, result, _god_image) = convert(TrimMode::None);
+
+        let con_header = ConHeader::read(File::open(&result.con_header_path).unwrap()).unwrap();
+        let dest_dir = dir.path().join("dest");
+        let file_layout = FileLayout::new(
+            &dest_dir,
+            &con_header.execution_info,
+            con_header.content_type,
+        );
+
+        fs::copy(file_layout.part_file_path(0), file_layout.part_file_path(1)).unwrap();
+        assert!(god2iso(&file_layout, 1, IsoType::Xsf, std::io::sink()).is_err());
+        assert!(GodReader::open_con_header(&result.con_header_path).is_err());
+
+        fs::remove_file(file_layout.part_file_path(0)).unwrap();
+        assert!(god2iso(&file_layout, 2, IsoType::Xsf, std::io::sink()).is_err());
+    }
+
+    #[test]
+    fn round_trip_full_rebuild() {
+        let (_dir, image, _result, god_image) = convert(TrimMode::FullRebuild);
+
+        let source_iso = IsoReader::read(Cursor::new(image)).unwrap();
+        let god_iso = IsoReader::read(Cursor::new(god_image)).unwrap();
+
+        let source_files: Vec<_> = source_iso.directory_table.walk().collect();
+        let god_files: Vec<_> = god_iso.directory_table.walk().collect();
+        assert_eq!(source_files.len(), god_files.len());
+
+        for (path, _) in source_files
+            .iter()
+            .filter(|(_, e)| e.subdirectory.is_none())
+        {
+            let read = |iso: &IsoReader<Cursor<Vec<u8>>>| {
+                let mut data = Vec::new();
+                let mut file = iso.get_entry(path).unwrap().unwrap();
+                std::io::Read::read_to_end(&mut file, &mut data).unwrap();
+                data
+            };
+            assert_eq!(read(&source_iso), read(&god_iso), "{path}");
+        }
+    }
+}
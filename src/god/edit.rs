@@ -0,0 +1,167 @@
+use std::fs;
+use std::io::{Cursor, Write};
+
+use anyhow::{Context, Error, bail};
+
+use tempfile::NamedTempFile;
+
+use super::*;
+
+/// Metadata changes for [`edit_con_header`]; `None` leaves a value as it is.
+#[derive(Clone, Debug, Default)]
+pub struct ConHeaderEdit {
+    pub game_title: Option<String>,
+    pub game_icon: Option<Vec<u8>>,
+    pub disc_number: Option<u8>,
+    pub disc_count: Option<u8>,
+}
+
+/// Rewrites the CON header of an existing GOD package. Data parts are not
+/// touched, since none of the editable fields are covered by their hashes.
+pub fn edit_con_header(file_layout: &FileLayout, edit: &ConHeaderEdit) -> Result<ConHeader, Error> {
+    let buffer = build_edited_con_header(file_layout, edit)?;
+    write_con_header(file_layout, &buffer)
+}
+
+/// Checks the edit and builds the new CON header, without writing it yet; for
+/// editing several packages at once, where none should be changed if any of
+/// them fails.
+pub fn build_edited_con_header(
+    file_layout: &FileLayout,
+    edit: &ConHeaderEdit,
+) -> Result<Vec<u8>, Error> {
+    let con_header_path = file_layout.con_header_file_path();
+
+    let buffer = fs::read(&con_header_path).context("error reading con header file")?;
+    let con_header = ConHeader::read(Cursor::new(&buffer)).context("error reading con header")?;
+
+    let mut builder = ConHeaderBuilder::from_bytes(buffer)?;
+
+    if let Some(game_title) = &edit.game_title {
+        check_game_title(game_title)?;
+        builder = builder.with_game_title(game_title);
+    }
+
+    if let Some(game_icon) = &edit.game_icon {
+        builder = builder.with_game_icon(Some(game_icon))?;
+    }
+
+    if edit.disc_number.is_some() || edit.disc_count.is_some() {
+        let mut exe_info = con_header.execution_info.clone();
+        exe_info.disc_number = edit.disc_number.unwrap_or(exe_info.disc_number);
+        exe_info.disc_count = edit.disc_count.unwrap_or(exe_info.disc_count);
+
+        if exe_info.disc_number == 0 || exe_info.disc_number > exe_info.disc_count {
+            bail!(
+                "invalid disc number {}/{}",
+                exe_info.disc_number,
+                exe_info.disc_count
+            );
+        }
+
+        builder = builder.with_execution_info(&exe_info);
+    }
+
+    Ok(builder.finalize())
+}
+
+/// Replaces the CON header file of a package with `buffer`, from
+/// [`build_edited_con_header`].
+pub fn write_con_header(file_layout: &FileLayout, buffer: &[u8]) -> Result<ConHeader, Error> {
+    let con_header_path = file_layout.con_header_file_path();
+
+    // write to a temporary file first, so that a failed write can not leave
+    // a half-written header behind
+    let con_header_dir = con_header_path
+        .parent()
+        .context("con header file has no parent directory")?;
+    let mut con_header_file =
+        NamedTempFile::new_in(con_header_dir).context("error creating con header file")?;
+    con_header_file
+        .write_all(buffer)
+        .context("error writing con header file")?;
+    con_header_file
+        .persist(&con_header_path)
+        .context("error replacing con header file")?;
+
+    ConHeader::read(Cursor::new(buffer)).context("error reading con header")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use crate::executable::TitleExecutionInfo;
+
+    fn execution_info() -> TitleExecutionInfo {
+        TitleExecutionInfo {
+            media_id: 0x12345678,
+            version: 0,
+            base_version: 0,
+            title_id: 0x54455354,
+            platform: 0,
+            executable_type: 0,
+            disc_number: 1,
+            disc_count: 1,
+        }
+    }
+
+    fn write_package(file_layout: &FileLayout) {
+        let buffer = ConHeaderBuilder::new()
+            .with_execution_info(&execution_info())
+            .with_content_type(ContentType::GamesOnDemand)
+            .with_game_title("Before")
+            .finalize();
+
+        let con_header_path = file_layout.con_header_file_path();
+        fs::create_dir_all(con_header_path.parent().unwrap()).unwrap();
+        fs::write(con_header_path, buffer).unwrap();
+    }
+
+    #[test]
+    fn edits_are_read_back() {
+        let dir = tempdir().unwrap();
+        let exe_info = execution_info();
+        let file_layout = FileLayout::new(dir.path(), &exe_info, ContentType::GamesOnDemand);
+        write_package(&file_layout);
+
+        let icon = b"\x89PNG\r\n\x1a\nicon".to_vec();
+        let edit = ConHeaderEdit {
+            game_title: Some("After".to_owned()),
+            game_icon: Some(icon.clone()),
+            disc_number: Some(2),
+            disc_count: Some(2),
+        };
+        edit_con_header(&file_layout, &edit).unwrap();
+
+        let buffer = fs::read(file_layout.con_header_file_path()).unwrap();
+        let con_header = ConHeader::read(Cursor::new(buffer)).unwrap();
+        assert_eq!(con_header.game_title, "After");
+        assert_eq!(con_header.game_icon, icon);
+        assert_eq!(con_header.title_icon, icon);
+        assert_eq!(con_header.execution_info.disc_number, 2);
+        assert_eq!(con_header.execution_info.disc_count, 2);
+        assert_eq!(con_header.execution_info.title_id, exe_info.title_id);
+    }
+
+    #[test]
+    fn invalid_disc_number_is_rejected() {
+        let dir = tempdir().unwrap();
+        let exe_info = execution_info();
+        let file_layout = FileLayout::new(dir.path(), &exe_info, ContentType::GamesOnDemand);
+        write_package(&file_layout);
+        let before = fs::read(file_layout.con_header_file_path()).unwrap();
+
+        let edit = ConHeaderEdit {
+            game_title: Some("After".to_owned()),
+            disc_number: Some(2),
+            ..Default::default()
+        };
+        assert!(edit_con_header(&file_layout, &edit).is_err());
+
+        let after = fs::read(file_layout.con_header_file_path()).unwrap();
+        assert_eq!(after, before);
+    }
+}
@@ -23,6 +23,15 @@ impl<'a> FileLayout<'a> {
         }
     }
 
+    /// The inverse of [`FileLayout::con_header_file_path`]: CON header files
+    /// live at `<base>/<title id>/<content type>/<media id>`.
+    pub fn base_path_of(con_header_file_path: &Path) -> &Path {
+        con_header_file_path
+            .ancestors()
+            .nth(3)
+            .unwrap_or(Path::new(""))
+    }
+
     fn title_id_string(&self) -> String {
         format!("{:08X}", self.exe_info.title_id)
     }
@@ -42,9 +51,12 @@ impl<'a> FileLayout<'a> {
         }
     }
 
+    pub fn title_dir_path(&self) -> PathBuf {
+        self.base_path.join(self.title_id_string())
+    }
+
     pub fn data_dir_path(&self) -> PathBuf {
-        self.base_path
-            .join(self.title_id_string())
+        self.title_dir_path()
             .join(self.content_type_string())
             .join(self.media_id_string() + ".data")
     }
@@ -54,8 +66,7 @@ impl<'a> FileLayout<'a> {
     }
 
     pub fn con_header_file_path(&self) -> PathBuf {
-        self.base_path
-            .join(self.title_id_string())
+        self.title_dir_path()
             .join(self.content_type_string())
             .join(self.media_id_string())
     }
@@ -0,0 +1,195 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{Context, Error, bail};
+
+use super::*;
+
+/// Data in a full part file, without its hash lists
+const PART_DATA_SIZE: u64 = BLOCKS_PER_PART * BLOCK_SIZE;
+
+/// Reads the data volume of a GOD package (the game partition of the disc it
+/// was converted from) out of its part files, skipping the hash lists that are
+/// interleaved with the data.
+///
+/// The hashes are not checked; use [`verify`] for that.
+pub struct GodReader {
+    parts: Vec<GodPart>,
+    size: u64,
+    position: u64,
+}
+
+struct GodPart {
+    file: File,
+    /// Size of the data in this part, up to [`PART_DATA_SIZE`]
+    data_size: u64,
+}
+
+impl GodReader {
+    /// Opens the `part_count` part files of the package, `Data0000` and on,
+    /// with the count taken from its CON header.
+    pub fn open(file_layout: &FileLayout, part_count: u64) -> Result<GodReader, Error> {
+        check_part_files(file_layout, part_count)?;
+
+        let files = (0..part_count)
+            .map(|part_index| file_layout.part_file_path(part_index))
+            .map(|path| {
+                File::open(&path).with_context(|| format!("error opening {}", path.display()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_files(files)
+    }
+
+    /// Opens the package that a CON header file belongs to.
+    pub fn open_con_header(con_header_path: &Path) -> Result<GodReader, Error> {
+        let con_header_file = File::open(con_header_path).context("error opening con header")?;
+        let con_header = ConHeader::read(con_header_file).context("error reading con header")?;
+
+        let file_layout = FileLayout::new(
+            FileLayout::base_path_of(con_header_path),
+            &con_header.execution_info,
+            con_header.content_type,
+        );
+
+        Self::open(&file_layout, con_header.part_count as u64)
+    }
+
+    /// Reads the part files one after another; all but the last one have to
+    /// be full.
+    pub fn from_files(files: Vec<File>) -> Result<GodReader, Error> {
+        let part_count = files.len();
+        let mut parts = Vec::with_capacity(part_count);
+
+        for (part_index, file) in files.into_iter().enumerate() {
+            let data_size = part_data_size(file.metadata()?.len())
+                .with_context(|| format!("error reading part {part_index}"))?;
+
+            if part_index + 1 < part_count && data_size < PART_DATA_SIZE {
+                bail!("part {part_index} is truncated");
+            }
+
+            parts.push(GodPart { file, data_size });
+        }
+
+        let size = parts.iter().map(|part| part.data_size).sum();
+
+        Ok(GodReader {
+            parts,
+            size,
+            position: 0,
+        })
+    }
+
+    /// Another reader over the same files, with its own position.
+    pub fn try_clone(&self) -> Result<GodReader, Error> {
+        let parts = self
+            .parts
+            .iter()
+            .map(|part| {
+                Ok(GodPart {
+                    file: part.file.try_clone()?,
+                    data_size: part.data_size,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(GodReader {
+            parts,
+            size: self.size,
+            position: 0,
+        })
+    }
+
+    /// Size of the data volume.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.parts.len()
+    }
+}
+
+/// Size of the data in a part file of the given length: a master hash list,
+/// then subparts of a sub hash list and up to [`SUBPART_SIZE`] bytes of data.
+fn part_data_size(file_size: u64) -> Result<u64, Error> {
+    let Some(subparts_size) = file_size.checked_sub(BLOCK_SIZE) else {
+        bail!("part file is too short to contain a master hash list");
+    };
+
+    let full_subpart_count = subparts_size / (BLOCK_SIZE + SUBPART_SIZE);
+    let last_subpart_size = subparts_size % (BLOCK_SIZE + SUBPART_SIZE);
+
+    if last_subpart_size > 0 && last_subpart_size <= BLOCK_SIZE {
+        bail!("part file ends in the middle of a sub hash list");
+    }
+
+    let data_size =
+        full_subpart_count * SUBPART_SIZE + last_subpart_size.saturating_sub(BLOCK_SIZE);
+
+    if data_size > PART_DATA_SIZE {
+        bail!("part file is too long: {file_size} bytes");
+    }
+
+    Ok(data_size)
+}
+
+impl Read for GodReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let part_index = self.position / PART_DATA_SIZE;
+        let part_position = self.position % PART_DATA_SIZE;
+
+        let Some(part) = usize::try_from(part_index)
+            .ok()
+            .and_then(|part_index| self.parts.get_mut(part_index))
+        else {
+            return Ok(0);
+        };
+
+        let subpart_index = part_position / SUBPART_SIZE;
+        let subpart_position = part_position % SUBPART_SIZE;
+
+        // reads stop at the end of the subpart, where the next hash list is
+        let remaining =
+            (SUBPART_SIZE - subpart_position).min(part.data_size.saturating_sub(part_position));
+        let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        // past the master hash list, the subparts before this one, and this
+        // one's sub hash list
+        let file_position = BLOCK_SIZE
+            + subpart_index * (BLOCK_SIZE + SUBPART_SIZE)
+            + BLOCK_SIZE
+            + subpart_position;
+
+        part.file.seek(SeekFrom::Start(file_position))?;
+        let len = part.file.read(&mut buf[..len])?;
+
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for GodReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.position)
+    }
+}
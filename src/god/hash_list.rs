@@ -40,6 +40,10 @@ impl HashList {
         Ok(HashList { buffer, len })
     }
 
+    pub fn hashes(&self) -> impl Iterator<Item = &[u8]> {
+        self.buffer[..self.len].chunks_exact(20)
+    }
+
     pub fn add_hash(&mut self, hash: &[u8; 20]) {
         self.buffer[self.len..self.len + 20].copy_from_slice(hash);
         self.len += 20;
@@ -1,19 +1,33 @@
 use std::io::{Read, Seek, SeekFrom, Write};
 
-use anyhow::Error;
+use anyhow::{Error, bail};
+
+use crate::iso::iso_type::IsoType;
 
 mod con_header;
 pub use con_header::*;
 
+mod converter;
+pub use converter::*;
+
+mod edit;
+pub use edit::*;
+
 mod file_layout;
 pub use file_layout::*;
 
 mod gdf_sector;
 pub use gdf_sector::*;
 
+mod god_reader;
+pub use god_reader::*;
+
 mod hash_list;
 pub use hash_list::*;
 
+mod verify;
+pub use verify::*;
+
 pub const BLOCKS_PER_PART: u64 = 0xa1c4;
 pub const BLOCKS_PER_SUBPART: u64 = 0xcc;
 pub const BLOCK_SIZE: u64 = 0x1000;
@@ -69,3 +83,51 @@ pub fn write_part<R: Read + Seek, W: Write + Seek>(
 
     Ok(())
 }
+
+/// Checks that the package has exactly `part_count` part files, as its CON
+/// header says; a stale extra part or a missing one is an error.
+pub fn check_part_files(file_layout: &FileLayout, part_count: u64) -> Result<(), Error> {
+    if part_count == 0 {
+        bail!("CON header lists no part files");
+    }
+
+    for part_index in 0..part_count {
+        let part_file_path = file_layout.part_file_path(part_index);
+        if !part_file_path.is_file() {
+            bail!(
+                "part file {} of {part_count} is missing: {}",
+                part_index + 1,
+                part_file_path.display()
+            );
+        }
+    }
+
+    let extra_part_file_path = file_layout.part_file_path(part_count);
+    if extra_part_file_path.exists() {
+        bail!(
+            "unexpected part file {}, CON header lists only {part_count}",
+            extra_part_file_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Writes the data of `part_count` part files, from the CON header, as an
+/// image of the given type.
+pub fn god2iso<W: Write>(
+    file_layout: &FileLayout,
+    part_count: u64,
+    iso_type: IsoType,
+    mut iso_image: W,
+) -> Result<u64, Error> {
+    let mut god_reader = GodReader::open(file_layout, part_count)?;
+
+    // the video partition is not part of the GOD package, so it is left zeroed
+    let root_offset = iso_type.root_offset();
+    std::io::copy(&mut std::io::repeat(0).take(root_offset), &mut iso_image)?;
+
+    let data_size = std::io::copy(&mut god_reader, &mut iso_image)?;
+
+    Ok(root_offset + data_size)
+}
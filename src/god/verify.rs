@@ -0,0 +1,432 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+
+use sha1::{Digest, Sha1};
+
+use anyhow::{Context, Error, bail};
+
+use rayon::prelude::*;
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    /// The SHA-1 at 0x032c does not match the rest of the CON header
+    ConHeaderHash,
+    /// The MHT hash in the CON header does not match part 0
+    MhtHash,
+    MissingPart {
+        part_index: u64,
+    },
+    TruncatedPart {
+        part_index: u64,
+    },
+    /// A part file beyond the part count in the CON header
+    ExtraPart {
+        part_index: u64,
+    },
+    /// A sub hash list does not match its entry in the master hash list
+    SubHashList {
+        part_index: u64,
+        subpart_index: u32,
+    },
+    /// The master hash list does not chain to the next part's one
+    NextPartHash {
+        part_index: u64,
+    },
+    Block {
+        part_index: u64,
+        subpart_index: u32,
+        block_index: u32,
+    },
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Corruption::ConHeaderHash => write!(f, "con header: header hash mismatch"),
+            Corruption::MhtHash => write!(f, "con header: MHT hash mismatch"),
+            Corruption::MissingPart { part_index } => {
+                write!(f, "part {part_index}: part file is missing")
+            }
+            Corruption::TruncatedPart { part_index } => {
+                write!(f, "part {part_index}: part file is truncated")
+            }
+            Corruption::ExtraPart { part_index } => {
+                write!(
+                    f,
+                    "part {part_index}: part file is not listed in con header"
+                )
+            }
+            Corruption::SubHashList {
+                part_index,
+                subpart_index,
+            } => write!(
+                f,
+                "part {part_index}, subpart {subpart_index}: sub hash list hash mismatch"
+            ),
+            Corruption::NextPartHash { part_index } => {
+                write!(f, "part {part_index}: next part MHT hash mismatch")
+            }
+            Corruption::Block {
+                part_index,
+                subpart_index,
+                block_index,
+            } => write!(
+                f,
+                "part {part_index}, subpart {subpart_index}, block {block_index}: block hash mismatch"
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VerifyReport {
+    pub part_count: u64,
+    pub corruptions: Vec<Corruption>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.corruptions.is_empty()
+    }
+}
+
+struct PartReport {
+    master_hash_list: Option<HashList>,
+    subpart_count: u32,
+    corruptions: Vec<Corruption>,
+}
+
+/// Re-reads a whole GOD package and checks every hash in it, from the data
+/// blocks up to the CON header. Parts are checked in parallel on the current
+/// rayon thread pool.
+pub fn verify(file_layout: &FileLayout) -> Result<VerifyReport, Error> {
+    let con_header =
+        fs::read(file_layout.con_header_file_path()).context("error reading con header file")?;
+
+    if con_header.len() < 0x0344 + 0xacbc {
+        bail!("con header file is too short");
+    }
+
+    let mut corruptions = Vec::new();
+
+    let header_digest: [u8; 20] = Sha1::digest(&con_header[0x0344..(0x0344 + 0xacbc)]).into();
+    if con_header[0x032c..0x0340] != header_digest {
+        corruptions.push(Corruption::ConHeaderHash);
+    }
+
+    let parsed_con_header = ConHeader::read(std::io::Cursor::new(&con_header))?;
+    let part_count = parsed_con_header.part_count as u64;
+
+    let part_reports = (0..part_count)
+        .into_par_iter()
+        .map(|part_index| {
+            let part_file = match File::open(file_layout.part_file_path(part_index)) {
+                Ok(part_file) => part_file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(PartReport {
+                        master_hash_list: None,
+                        subpart_count: 0,
+                        corruptions: vec![Corruption::MissingPart { part_index }],
+                    });
+                }
+                Err(e) => return Err(Error::from(e).context("error opening part file")),
+            };
+
+            verify_part(part_file, part_index).context("error reading part file")
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    match part_reports
+        .first()
+        .and_then(|r| r.master_hash_list.as_ref())
+    {
+        Some(mht) if parsed_con_header.mht_hash == mht.digest() => {}
+        _ => corruptions.push(Corruption::MhtHash),
+    }
+
+    for (part_index, part_report) in part_reports.iter().enumerate() {
+        corruptions.extend_from_slice(&part_report.corruptions);
+
+        let Some(next_part_report) = part_reports.get(part_index + 1) else {
+            continue;
+        };
+
+        let (Some(mht), Some(next_mht)) = (
+            &part_report.master_hash_list,
+            &next_part_report.master_hash_list,
+        ) else {
+            continue;
+        };
+
+        let next_mht_hash = mht.hashes().nth(part_report.subpart_count as usize);
+        if next_mht_hash != Some(&next_mht.digest()[..]) {
+            corruptions.push(Corruption::NextPartHash {
+                part_index: part_index as u64,
+            });
+        }
+    }
+
+    // e.g. left over from an earlier conversion of a larger image
+    for part_index in part_count.. {
+        if !file_layout.part_file_path(part_index).exists() {
+            break;
+        }
+        corruptions.push(Corruption::ExtraPart { part_index });
+    }
+
+    Ok(VerifyReport {
+        part_count,
+        corruptions,
+    })
+}
+
+fn verify_part<R: Read>(mut part_file: R, part_index: u64) -> Result<PartReport, Error> {
+    let mut report = PartReport {
+        master_hash_list: None,
+        subpart_count: 0,
+        corruptions: Vec::new(),
+    };
+
+    let mut hash_list_buf = Vec::with_capacity(BLOCK_SIZE as usize);
+    let mut subpart_buf = Vec::with_capacity(SUBPART_SIZE as usize);
+
+    part_file
+        .by_ref()
+        .take(BLOCK_SIZE)
+        .read_to_end(&mut hash_list_buf)?;
+
+    if hash_list_buf.len() < BLOCK_SIZE as usize {
+        report
+            .corruptions
+            .push(Corruption::TruncatedPart { part_index });
+        return Ok(report);
+    }
+
+    let master_hash_list = HashList::read(&hash_list_buf[..])?;
+
+    for subpart_index in 0..SUBPARTS_PER_PART {
+        hash_list_buf.clear();
+        part_file
+            .by_ref()
+            .take(BLOCK_SIZE)
+            .read_to_end(&mut hash_list_buf)?;
+
+        if hash_list_buf.is_empty() {
+            break;
+        }
+
+        if hash_list_buf.len() < BLOCK_SIZE as usize {
+            report
+                .corruptions
+                .push(Corruption::TruncatedPart { part_index });
+            break;
+        }
+
+        let sub_hash_list = HashList::read(&hash_list_buf[..])?;
+        report.subpart_count += 1;
+
+        let sub_hash_list_hash = master_hash_list.hashes().nth(subpart_index as usize);
+        if sub_hash_list_hash != Some(&sub_hash_list.digest()[..]) {
+            report.corruptions.push(Corruption::SubHashList {
+                part_index,
+                subpart_index,
+            });
+        }
+
+        subpart_buf.clear();
+        part_file
+            .by_ref()
+            .take(SUBPART_SIZE)
+            .read_to_end(&mut subpart_buf)?;
+
+        let mut block_hashes = sub_hash_list.hashes();
+
+        for (block_index, block) in subpart_buf.chunks(BLOCK_SIZE as usize).enumerate() {
+            let block_hash: [u8; 20] = Sha1::digest(block).into();
+            if block_hashes.next() != Some(&block_hash[..]) {
+                report.corruptions.push(Corruption::Block {
+                    part_index,
+                    subpart_index,
+                    block_index: block_index as u32,
+                });
+            }
+        }
+
+        if subpart_buf.len() < SUBPART_SIZE as usize {
+            break;
+        }
+    }
+
+    report.master_hash_list = Some(master_hash_list);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+
+    use tempfile::{TempDir, tempdir};
+
+    use crate::executable::TitleExecutionInfo;
+
+    const EXE_INFO: TitleExecutionInfo = TitleExecutionInfo {
+        media_id: 0x12345678,
+        version: 0,
+        base_version: 0,
+        title_id: 0x54455354,
+        platform: 0,
+        executable_type: 0,
+        disc_number: 1,
+        disc_count: 1,
+    };
+
+    /// A package of two parts, with a single block each.
+    fn package(dir: &TempDir) -> FileLayout<'_> {
+        let file_layout = FileLayout::new(dir.path(), &EXE_INFO, ContentType::GamesOnDemand);
+        fs::create_dir_all(file_layout.data_dir_path()).unwrap();
+
+        for part_index in 0..2 {
+            let block = vec![part_index as u8 + 1; BLOCK_SIZE as usize];
+            let part_file = File::create(file_layout.part_file_path(part_index)).unwrap();
+            write_part(Cursor::new(block), 0, part_file).unwrap();
+        }
+
+        let next_mht = read_mht(&file_layout, 1);
+        let mut mht = read_mht(&file_layout, 0);
+        mht.add_hash(&next_mht.digest());
+        let mut part_file = File::options()
+            .write(true)
+            .open(file_layout.part_file_path(0))
+            .unwrap();
+        mht.write(&mut part_file).unwrap();
+
+        let con_header = ConHeaderBuilder::new()
+            .with_execution_info(&EXE_INFO)
+            .with_block_counts(2, 0)
+            .with_data_parts_info(2, 6 * BLOCK_SIZE)
+            .with_content_type(ContentType::GamesOnDemand)
+            .with_mht_hash(&mht.digest())
+            .finalize();
+        fs::write(file_layout.con_header_file_path(), con_header).unwrap();
+
+        file_layout
+    }
+
+    fn read_mht(file_layout: &FileLayout, part_index: u64) -> HashList {
+        HashList::read(File::open(file_layout.part_file_path(part_index)).unwrap()).unwrap()
+    }
+
+    fn flip_byte(path: &std::path::Path, offset: u64) {
+        let mut file = File::options().read(true).write(true).open(path).unwrap();
+        let mut byte = [0u8];
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.read_exact(&mut byte).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[byte[0] ^ 0xff]).unwrap();
+    }
+
+    fn corruptions(file_layout: &FileLayout) -> Vec<Corruption> {
+        verify(file_layout).unwrap().corruptions
+    }
+
+    #[test]
+    fn intact_package_is_ok() {
+        let dir = tempdir().unwrap();
+        let file_layout = package(&dir);
+        let report = verify(&file_layout).unwrap();
+        assert_eq!(report.part_count, 2);
+        assert!(report.is_ok(), "{:?}", report.corruptions);
+    }
+
+    #[test]
+    fn corrupt_data_block_is_found() {
+        let dir = tempdir().unwrap();
+        let file_layout = package(&dir);
+        flip_byte(&file_layout.part_file_path(1), 2 * BLOCK_SIZE + 10);
+        assert_eq!(
+            corruptions(&file_layout),
+            [Corruption::Block {
+                part_index: 1,
+                subpart_index: 0,
+                block_index: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn corrupt_sub_hash_list_is_found() {
+        let dir = tempdir().unwrap();
+        let file_layout = package(&dir);
+        flip_byte(&file_layout.part_file_path(1), 2 * BLOCK_SIZE - 1);
+        assert_eq!(
+            corruptions(&file_layout),
+            [Corruption::SubHashList {
+                part_index: 1,
+                subpart_index: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn corrupt_master_hash_list_is_found() {
+        let dir = tempdir().unwrap();
+        let file_layout = package(&dir);
+        flip_byte(&file_layout.part_file_path(0), 0);
+        assert_eq!(
+            corruptions(&file_layout),
+            [
+                Corruption::MhtHash,
+                Corruption::SubHashList {
+                    part_index: 0,
+                    subpart_index: 0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn broken_mht_chain_is_found() {
+        let dir = tempdir().unwrap();
+        let file_layout = package(&dir);
+        flip_byte(&file_layout.part_file_path(1), BLOCK_SIZE - 1);
+        assert_eq!(
+            corruptions(&file_layout),
+            [Corruption::NextPartHash { part_index: 0 }]
+        );
+    }
+
+    #[test]
+    fn corrupt_con_header_hash_is_found() {
+        let dir = tempdir().unwrap();
+        let file_layout = package(&dir);
+        flip_byte(&file_layout.con_header_file_path(), 0x032c);
+        assert_eq!(corruptions(&file_layout), [Corruption::ConHeaderHash]);
+    }
+
+    #[test]
+    fn missing_part_is_found() {
+        let dir = tempdir().unwrap();
+        let file_layout = package(&dir);
+        fs::remove_file(file_layout.part_file_path(1)).unwrap();
+        assert_eq!(
+            corruptions(&file_layout),
+            [Corruption::MissingPart { part_index: 1 }]
+        );
+    }
+
+    #[test]
+    fn extra_part_is_found() {
+        let dir = tempdir().unwrap();
+        let file_layout = package(&dir);
+        fs::copy(file_layout.part_file_path(1), file_layout.part_file_path(2)).unwrap();
+        assert_eq!(
+            corruptions(&file_layout),
+            [Corruption::ExtraPart { part_index: 2 }]
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+use byteorder::{LE, ReadBytesExt};
+
+use anyhow::{Error, bail};
+
+use crate::Iso2GodError;
+
+use super::lz4;
+
+// real images use 2048, anything much larger is more likely garbage
+const MAX_BLOCK_SIZE: u32 = 0x0010_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressedFormat {
+    /// LZ4-compressed 2048-byte sectors, as made by Repackinator for original
+    /// Xbox images
+    Cci,
+    /// Compressed ISO, with deflate (version 1) or LZ4 (version 2) blocks
+    Cso,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BlockCodec {
+    Stored,
+    /// LZ4 data after a byte with the length of the padding at the end
+    CciLz4,
+    Lz4,
+    Deflate,
+}
+
+#[derive(Clone, Debug)]
+struct Block {
+    offset: u64,
+    len: u64,
+    codec: BlockCodec,
+}
+
+/// Reads a CCI or CSO image as the plain image inside of it, decompressing
+/// blocks as they are read.
+pub struct CompressedReader<R: Read + Seek> {
+    reader: R,
+    pub format: CompressedFormat,
+    block_size: u64,
+    size: u64,
+    blocks: Arc<[Block]>,
+    position: u64,
+    /// The last decompressed block, by index
+    cache: Option<(usize, Vec<u8>)>,
+}
+
+impl CompressedFormat {
+    /// Detects the format by its magic bytes; `None` means an uncompressed image.
+    pub fn detect<R: Read + Seek>(mut reader: R) -> Result<Option<CompressedFormat>, Error> {
+        let mut magic_bytes = [0u8; 4];
+
+        reader.seek(SeekFrom::Start(0))?;
+        match reader.read_exact(&mut magic_bytes) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(match &magic_bytes {
+            b"CCIM" => Some(CompressedFormat::Cci),
+            b"CISO" => Some(CompressedFormat::Cso),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for CompressedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressedFormat::Cci => write!(f, "CCI"),
+            CompressedFormat::Cso => write!(f, "CSO"),
+        }
+    }
+}
+
+impl<R: Read + Seek> CompressedReader<R> {
+    pub fn new(mut reader: R) -> Result<CompressedReader<R>, Error> {
+        let Some(format) = CompressedFormat::detect(&mut reader)? else {
+            return Err(Iso2GodError::BadMagic {
+                offset: 0,
+                expected: "'CCIM' or 'CISO'",
+            }
+            .into());
+        };
+
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(4))?;
+
+        // Based on https://github.com/Team-Resurgent/Repackinator (CCI) and
+        // https://github.com/MakeMHz/xbox-iso-vfs (CSO)
+        let (index_offset, size, block_size, version, index_shift) = match format {
+            CompressedFormat::Cci => {
+                let _header_size = reader.read_u32::<LE>()?;
+                let size = reader.read_u64::<LE>()?;
+                let index_offset = reader.read_u64::<LE>()?;
+                let block_size = reader.read_u32::<LE>()?;
+                let version = reader.read_u8()?;
+                let index_shift = reader.read_u8()?;
+                (index_offset, size, block_size, version, index_shift)
+            }
+            CompressedFormat::Cso => {
+                let header_size = reader.read_u32::<LE>()?;
+                let size = reader.read_u64::<LE>()?;
+                let block_size = reader.read_u32::<LE>()?;
+                let version = reader.read_u8()?;
+                let index_shift = reader.read_u8()?;
+                (
+                    header_size.max(0x18) as u64,
+                    size,
+                    block_size,
+                    version,
+                    index_shift,
+                )
+            }
+        };
+
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE || index_shift > 31 {
+            bail!("invalid {format} header: block size {block_size}, index shift {index_shift}");
+        }
+
+        let block_count = size.div_ceil(block_size as u64);
+
+        // the index has to fit in the file, which also bounds its allocation
+        let index_end = block_count
+            .checked_add(1)
+            .and_then(|entry_count| entry_count.checked_mul(4))
+            .and_then(|index_len| index_offset.checked_add(index_len));
+        if index_end.is_none_or(|end| end > file_size) {
+            bail!("{format} block index is out of bounds");
+        }
+
+        reader.seek(SeekFrom::Start(index_offset))?;
+        let mut index = vec![0u32; (block_count + 1) as usize];
+        reader.read_u32_into::<LE>(&mut index)?;
+
+        let blocks = index
+            .windows(2)
+            .map(|entries| {
+                let offset = ((entries[0] & 0x7fff_ffff) as u64) << index_shift;
+                let end = ((entries[1] & 0x7fff_ffff) as u64) << index_shift;
+                let flag = entries[0] & 0x8000_0000 != 0;
+
+                if end < offset || end > file_size {
+                    bail!("{format} block at {offset:#x} is out of bounds");
+                }
+
+                // blocks are at most a little larger than their data, when
+                // stored with padding; this bounds what reading one allocates
+                let len = end - offset;
+                if len > 2 * block_size as u64 {
+                    bail!("{format} block at {offset:#x} is too long: {len} bytes");
+                }
+
+                // the flag means compressed for CCI, but stored for CSO
+                let codec = match format {
+                    CompressedFormat::Cci if flag => BlockCodec::CciLz4,
+                    CompressedFormat::Cso if !flag && len < block_size as u64 => {
+                        if version >= 2 {
+                            BlockCodec::Lz4
+                        } else {
+                            BlockCodec::Deflate
+                        }
+                    }
+                    _ => BlockCodec::Stored,
+                };
+
+                Ok(Block { offset, len, codec })
+            })
+            .collect::<Result<Arc<[Block]>, Error>>()?;
+
+        Ok(CompressedReader {
+            reader,
+            format,
+            block_size: block_size as u64,
+            size,
+            blocks,
+            position: 0,
+            cache: None,
+        })
+    }
+
+    /// Another reader over the same image, sharing the block index.
+    pub fn with_reader<S: Read + Seek>(&self, reader: S) -> CompressedReader<S> {
+        CompressedReader {
+            reader,
+            format: self.format,
+            block_size: self.block_size,
+            size: self.size,
+            blocks: self.blocks.clone(),
+            position: 0,
+            cache: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Size of the uncompressed image.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_block(&mut self, index: usize) -> Result<Vec<u8>, Error> {
+        let block = &self.blocks[index];
+
+        let block_start = index as u64 * self.block_size;
+        let block_size = self.block_size.min(self.size - block_start) as usize;
+
+        self.reader.seek(SeekFrom::Start(block.offset))?;
+        let mut data = vec![0u8; block.len as usize];
+        self.reader.read_exact(&mut data)?;
+
+        let mut output = vec![0u8; block_size];
+
+        match block.codec {
+            BlockCodec::Stored => {
+                if data.len() < block_size {
+                    bail!("stored block {index} is truncated");
+                }
+                output.copy_from_slice(&data[..block_size]);
+            }
+            BlockCodec::CciLz4 => {
+                let Some((&padding, data)) = data.split_first() else {
+                    bail!("compressed block {index} is empty");
+                };
+                let data = &data[..data.len().saturating_sub(padding as usize)];
+                lz4::decompress_block(data, &mut output)?;
+            }
+            BlockCodec::Lz4 => lz4::decompress_block(&data, &mut output)?,
+            BlockCodec::Deflate => {
+                let status = miniz_oxide::inflate::decompress_slice_iter_to_slice(
+                    &mut output,
+                    std::iter::once(&data[..]),
+                    false,
+                    true,
+                );
+                match status {
+                    Ok(len) if len == block_size => {}
+                    _ => bail!("error inflating block {index}"),
+                }
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+impl<R: Read + Seek> Read for CompressedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let index = (self.position / self.block_size) as usize;
+
+        let block = match self.cache.take() {
+            Some((cached_index, block)) if cached_index == index => block,
+            _ => self
+                .read_block(index)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+        };
+
+        let block_position = (self.position % self.block_size) as usize;
+        let len = buf.len().min(block.len() - block_position);
+        buf[..len].copy_from_slice(&block[block_position..block_position + len]);
+
+        self.cache = Some((index, block));
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for CompressedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    const BLOCK_SIZE: usize = 0x800;
+
+    /// "ab" repeated over a whole block, as LZ4: two literals, then a match of
+    /// 15 + 255 * 7 + 242 + 4 bytes from 2 back.
+    const LZ4_BLOCK: &[u8] = &[
+        0x2f, b'a', b'b', 2, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xf2,
+    ];
+
+    fn lz4_block_data() -> Vec<u8> {
+        b"ab".repeat(BLOCK_SIZE / 2)
+    }
+
+    fn stored_block_data() -> Vec<u8> {
+        (0..BLOCK_SIZE).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// A CCI image of the given blocks, which are LZ4 compressed if flagged.
+    fn cci(blocks: &[(bool, Vec<u8>)], size: u64) -> Vec<u8> {
+        let mut image = Vec::new();
+        image.extend_from_slice(b"CCIM");
+        image.extend_from_slice(&0x20u32.to_le_bytes());
+        image.extend_from_slice(&size.to_le_bytes());
+        image.extend_from_slice(&0u64.to_le_bytes()); // index offset, below
+        image.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        image.extend_from_slice(&[1, 0, 0, 0]);
+
+        let mut index = Vec::new();
+        for (compressed, data) in blocks {
+            index.push(image.len() as u32 | if *compressed { 0x8000_0000 } else { 0 });
+            image.extend_from_slice(data);
+        }
+        index.push(image.len() as u32);
+
+        let index_offset = image.len() as u64;
+        image[0x10..0x18].copy_from_slice(&index_offset.to_le_bytes());
+        for entry in index {
+            image.extend_from_slice(&entry.to_le_bytes());
+        }
+        image
+    }
+
+    /// A CSO image of the given blocks, which are stored if flagged.
+    fn cso(version: u8, blocks: &[(bool, Vec<u8>)], size: u64) -> Vec<u8> {
+        let mut image = Vec::new();
+        image.extend_from_slice(b"CISO");
+        image.extend_from_slice(&0x18u32.to_le_bytes());
+        image.extend_from_slice(&size.to_le_bytes());
+        image.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        image.extend_from_slice(&[version, 0, 0, 0]);
+
+        let mut offset = (0x18 + 4 * (blocks.len() + 1)) as u32;
+        for (stored, data) in blocks {
+            let entry = offset | if *stored { 0x8000_0000 } else { 0 };
+            image.extend_from_slice(&entry.to_le_bytes());
+            offset += data.len() as u32;
+        }
+        image.extend_from_slice(&offset.to_le_bytes());
+
+        for (_, data) in blocks {
+            image.extend_from_slice(data);
+        }
+        image
+    }
+
+    fn read_all(image: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let mut reader = CompressedReader::new(Cursor::new(image))?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        assert_eq!(data.len() as u64, reader.size());
+        Ok(data)
+    }
+
+    fn expected_data() -> Vec<u8> {
+        [lz4_block_data(), stored_block_data()].concat()
+    }
+
+    #[test]
+    fn cci_round_trip() {
+        // the first byte of a compressed block is the length of the padding
+        // after it
+        let mut lz4_block = vec![3];
+        lz4_block.extend_from_slice(LZ4_BLOCK);
+        lz4_block.extend_from_slice(&[0xaa; 3]);
+
+        let image = cci(&[(true, lz4_block), (false, stored_block_data())], 0x1000);
+        assert_eq!(
+            CompressedFormat::detect(Cursor::new(&image)).unwrap(),
+            Some(CompressedFormat::Cci)
+        );
+        assert_eq!(read_all(image).unwrap(), expected_data());
+    }
+
+    #[test]
+    fn cso_v1_round_trip() {
+        let deflate_block = miniz_oxide::deflate::compress_to_vec(&lz4_block_data(), 6);
+
+        let image = cso(
+            1,
+            &[(false, deflate_block), (true, stored_block_data())],
+            0x1000,
+        );
+        assert_eq!(
+            CompressedFormat::detect(Cursor::new(&image)).unwrap(),
+            Some(CompressedFormat::Cso)
+        );
+        assert_eq!(read_all(image).unwrap(), expected_data());
+    }
+
+    #[test]
+    fn cso_v2_round_trip() {
+        let image = cso(
+            2,
+            &[(false, LZ4_BLOCK.to_vec()), (true, stored_block_data())],
+            0x1000,
+        );
+        assert_eq!(read_all(image).unwrap(), expected_data());
+    }
+
+    #[test]
+    fn reads_can_start_inside_a_block() {
+        let image = cso(
+            2,
+            &[(false, LZ4_BLOCK.to_vec()), (true, stored_block_data())],
+            0x1000,
+        );
+        let mut reader = CompressedReader::new(Cursor::new(image)).unwrap();
+
+        let mut buf = [0u8; 4];
+        reader.seek(SeekFrom::Start(0x7fe)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [b'a', b'b', 0, 1]);
+    }
+
+    #[test]
+    fn index_out_of_bounds_is_rejected() {
+        let mut image = cci(&[(false, stored_block_data())], 0x800);
+        let index_offset = image.len() as u64;
+        image[0x10..0x18].copy_from_slice(&index_offset.to_le_bytes());
+
+        let err = CompressedReader::new(Cursor::new(image)).err().unwrap();
+        assert!(err.to_string().contains("index is out of bounds"), "{err}");
+    }
+
+    #[test]
+    fn index_entry_out_of_bounds_is_rejected() {
+        let mut image = cso(2, &[(true, stored_block_data())], 0x800);
+        let end = image.len() as u32 + 1;
+        image[0x1c..0x20].copy_from_slice(&end.to_le_bytes());
+
+        let err = CompressedReader::new(Cursor::new(image)).err().unwrap();
+        assert!(err.to_string().contains("out of bounds"), "{err}");
+    }
+
+    #[test]
+    fn oversized_index_entry_is_rejected() {
+        let image = cso(2, &[(true, vec![0; 3 * BLOCK_SIZE])], 0x800);
+
+        let err = CompressedReader::new(Cursor::new(image)).err().unwrap();
+        assert!(err.to_string().contains("too long"), "{err}");
+    }
+}
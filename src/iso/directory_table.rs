@@ -1,17 +1,33 @@
 use byteorder::{LE, ReadBytesExt};
 
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::io::{Read, Seek, SeekFrom};
 
 use bitflags::bitflags;
 
-use anyhow::Error;
+use anyhow::{Error, bail};
 
 use super::*;
 
+/// Directories nested deeper than this are rejected, rather than risking a
+/// stack overflow on a corrupt image.
+const MAX_DIRECTORY_DEPTH: usize = 64;
+
+/// Much larger than any directory on a real disc
+const MAX_DIRECTORY_SIZE: u32 = 0x0100_0000;
+
+/// Real discs have tens of thousands of entries at most; this bounds the
+/// memory a corrupt image can make us allocate.
+const MAX_ENTRY_COUNT: usize = 0x0010_0000;
+
 pub struct DirectoryTable {
     pub sector: u32,
     pub size: u32,
     pub entries: Vec<DirectoryEntry>,
+    /// Offset of each entry from the start of the table, in 4-byte units, as
+    /// used by the subtree links
+    entry_offsets: Vec<u16>,
 }
 
 pub struct DirectoryEntry {
@@ -42,52 +58,269 @@ impl DirectoryTable {
         mut reader: R,
         volume: &VolumeDescriptor,
     ) -> Result<DirectoryTable, Error> {
+        let mut state = ReadState {
+            visited_sectors: HashSet::new(),
+            entry_count: 0,
+        };
+
         Self::read(
             &mut reader,
             volume,
             volume.root_directory_sector,
             volume.root_directory_size,
+            1,
+            &mut state,
         )
     }
 
+    /// Reads a table and, recursively, its subdirectories. A table that is
+    /// outside of the volume, or that overlaps one that was already read (so
+    /// that directories could form a cycle), is an error.
     fn read<R: Read + Seek>(
         reader: &mut R,
         volume: &VolumeDescriptor,
         sector: u32,
         size: u32,
+        depth: usize,
+        state: &mut ReadState,
     ) -> Result<DirectoryTable, Error> {
-        let mut entries = Vec::<DirectoryEntry>::new();
+        if depth > MAX_DIRECTORY_DEPTH {
+            bail!("directories are nested more than {MAX_DIRECTORY_DEPTH} levels deep");
+        }
+
+        if size > MAX_DIRECTORY_SIZE {
+            bail!("directory table at sector {sector} is too large: {size} bytes");
+        }
 
         let sector_count = size.div_ceil(SECTOR_SIZE as u32);
-        for sector_index in 0..sector_count {
-            let sector_position =
-                ((sector + sector_index) as u64) * volume.sector_size + volume.root_offset;
+
+        // empty directories may have any sector
+        if sector_count > 0 && (sector as u64) + (sector_count as u64) > volume.volume_sectors {
+            bail!(
+                "directory table at sector {sector} ({size} bytes) is outside of the volume ({} sectors)",
+                volume.volume_sectors
+            );
+        }
+
+        let sectors = (sector as u64)..(sector as u64) + (sector_count as u64);
+        for table_sector in sectors.clone() {
+            if !state.visited_sectors.insert(table_sector) {
+                bail!("directory table at sector {sector} overlaps another one");
+            }
+        }
+
+        let mut entries = Vec::<DirectoryEntry>::new();
+        let mut entry_offsets = Vec::new();
+
+        for (sector_index, table_sector) in sectors.enumerate() {
+            let sector_position = table_sector * volume.sector_size + volume.root_offset;
             reader.seek(SeekFrom::Start(sector_position))?;
 
-            while let Some(entry) = DirectoryEntry::read(reader, volume)? {
+            let mut offset = (sector_index as u64) * volume.sector_size;
+            let sector_end = offset + volume.sector_size;
+
+            while offset < sector_end {
+                let Some(entry) = DirectoryEntry::read(reader)? else {
+                    break;
+                };
+
+                state.entry_count += 1;
+                if state.entry_count > MAX_ENTRY_COUNT {
+                    bail!("image has more than {MAX_ENTRY_COUNT} directory entries");
+                }
+
+                // links are 16 bits, so entries further out cannot be linked to
+                entry_offsets.push(u16::try_from(offset / 4).unwrap_or(u16::MAX));
                 entries.push(entry);
+
+                offset = reader.stream_position()? - sector_position
+                    + (sector_index as u64) * volume.sector_size;
             }
         }
 
-        Ok(DirectoryTable {
+        for entry in entries.iter_mut().filter(|e| e.is_directory()) {
+            entry.subdirectory = Some(Self::read(
+                reader,
+                volume,
+                entry.sector,
+                entry.size,
+                depth + 1,
+                state,
+            )?);
+        }
+
+        let table = DirectoryTable {
             sector,
             size,
             entries,
-        })
+            entry_offsets,
+        };
+
+        table.check_tree()?;
+
+        Ok(table)
     }
 
+    /// Looks up an entry by name (ignoring ascii case), through the binary
+    /// search tree the table's entries form. Some authoring tools sort names
+    /// differently, so an entry the tree does not lead to is searched for
+    /// entry by entry.
     pub fn get_entry(&self, name: &str) -> Option<&DirectoryEntry> {
-        self.entries
-            .iter()
-            .find(|e| e.name.eq_ignore_ascii_case(name))
+        self.find_in_tree(name).or_else(|| {
+            self.entries
+                .iter()
+                .find(|entry| compare_names(name, &entry.name) == Ordering::Equal)
+        })
+    }
+
+    fn find_in_tree(&self, name: &str) -> Option<&DirectoryEntry> {
+        let mut index = self.root_index()?;
+
+        // `check_tree` made sure that this ends, but better safe than sorry
+        for _ in 0..self.entries.len() {
+            let entry = &self.entries[index];
+
+            let link = match compare_names(name, &entry.name) {
+                Ordering::Equal => return Some(entry),
+                Ordering::Less => entry.subtree_left,
+                Ordering::Greater => entry.subtree_right,
+            };
+
+            index = self.link_index(link)?;
+        }
+
+        None
+    }
+
+    fn root_index(&self) -> Option<usize> {
+        match self.entry_offsets.first() {
+            Some(0) => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Index of the entry a subtree link points to; `None` for no subtree.
+    fn link_index(&self, link: u16) -> Option<usize> {
+        if link == 0 {
+            return None;
+        }
+        self.entry_offsets.binary_search(&link).ok()
+    }
+
+    /// Checks that all subtree links point at entries of this table, and that
+    /// they form a tree: no entry is reachable from the root twice.
+    fn check_tree(&self) -> Result<(), Error> {
+        let Some(root_index) = self.root_index() else {
+            return Ok(());
+        };
+
+        let mut seen = vec![false; self.entries.len()];
+        let mut stack = vec![root_index];
+
+        while let Some(index) = stack.pop() {
+            if std::mem::replace(&mut seen[index], true) {
+                bail!(
+                    "directory table at sector {} has a cycle in its search tree",
+                    self.sector
+                );
+            }
+
+            let entry = &self.entries[index];
+
+            for link in [entry.subtree_left, entry.subtree_right] {
+                if link == 0 {
+                    continue;
+                }
+
+                let Some(child_index) = self.link_index(link) else {
+                    bail!(
+                        "entry {:?} in directory table at sector {} links to invalid offset {:#x}",
+                        entry.name,
+                        self.sector,
+                        (link as u32) * 4
+                    );
+                };
+
+                stack.push(child_index);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Iterates over all entries in this table and its subdirectories,
+    /// depth-first, with each directory followed by its contents.
+    pub fn walk(&self) -> Walk<'_> {
+        Walk {
+            stack: vec![(WindowsPath { components: vec![] }, self.entries.iter())],
+            files_only: false,
+            max_depth: usize::MAX,
+        }
+    }
+}
+
+/// What [`DirectoryTable::read`] keeps track of across all tables of an image.
+struct ReadState {
+    visited_sectors: HashSet<u64>,
+    entry_count: usize,
+}
+
+/// Iterator returned by [`DirectoryTable::walk`], yielding full paths
+/// relative to the walked table.
+pub struct Walk<'a> {
+    stack: Vec<(WindowsPath, std::slice::Iter<'a, DirectoryEntry>)>,
+    files_only: bool,
+    max_depth: usize,
+}
+
+impl Walk<'_> {
+    /// Skips directory entries, but still descends into them.
+    pub fn files_only(mut self) -> Self {
+        self.files_only = true;
+        self
+    }
+
+    /// Only yields entries up to this depth; entries of the walked table
+    /// itself are at depth 1.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (WindowsPath, &'a DirectoryEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let depth = self.stack.len();
+            let (dir_path, entries) = self.stack.last_mut()?;
+
+            let Some(entry) = entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+
+            let path = dir_path.join(&entry.name);
+
+            if let Some(subdir) = &entry.subdirectory {
+                if depth < self.max_depth {
+                    self.stack.push((path.clone(), subdir.entries.iter()));
+                }
+
+                if self.files_only {
+                    continue;
+                }
+            }
+
+            return Some((path, entry));
+        }
     }
 }
 
 impl DirectoryEntry {
-    fn read<R: Read + Seek>(
-        reader: &mut R,
-        volume: &VolumeDescriptor,
-    ) -> Result<Option<DirectoryEntry>, Error> {
+    /// Reads an entry, without its subdirectory; `None` at the end of a sector.
+    fn read<R: Read + Seek>(reader: &mut R) -> Result<Option<DirectoryEntry>, Error> {
         let subtree_left = reader.read_u16::<LE>()?;
         let subtree_right = reader.read_u16::<LE>()?;
 
@@ -98,15 +331,15 @@ impl DirectoryEntry {
         let sector = reader.read_u32::<LE>()?;
         let size = reader.read_u32::<LE>()?;
 
-        // This entry is empty
-        if size == 0 {
-            return Ok(None);
-        }
-
         let attributes = DirectoryEntryAttributes::from_bits_truncate(reader.read_u8()?);
 
         let name_length = reader.read_u8()?;
 
+        // This entry is empty (zero padding); empty files still have a name
+        if size == 0 && name_length == 0 {
+            return Ok(None);
+        }
+
         let mut name = vec![0_u8; name_length as usize];
         reader.read_exact(&mut name)?;
         let name = String::from_utf8_lossy(&name).into_owned();
@@ -114,16 +347,6 @@ impl DirectoryEntry {
         let alignment_mismatch = ((4 - reader.stream_position()? % 4) % 4) as i64;
         reader.seek(SeekFrom::Current(alignment_mismatch))?;
 
-        let is_directory = attributes.contains(DirectoryEntryAttributes::DIRECTORY);
-        let subdirectory = if is_directory {
-            let reader_position = reader.stream_position()?;
-            let subdir = DirectoryTable::read(reader, volume, sector, size)?;
-            reader.seek(SeekFrom::Start(reader_position))?;
-            Some(subdir)
-        } else {
-            None
-        };
-
         Ok(Some(DirectoryEntry {
             subtree_left,
             subtree_right,
@@ -132,7 +355,7 @@ impl DirectoryEntry {
             attributes,
             name_length,
             name,
-            subdirectory,
+            subdirectory: None,
         }))
     }
 
@@ -141,3 +364,136 @@ impl DirectoryEntry {
             .contains(DirectoryEntryAttributes::DIRECTORY)
     }
 }
+
+/// Orders names the way GDF search trees are sorted: by bytes, ignoring
+/// ascii case.
+fn compare_names(a: &str, b: &str) -> Ordering {
+    let a = a.bytes().map(|c| c.to_ascii_uppercase());
+    let b = b.bytes().map(|c| c.to_ascii_uppercase());
+    a.cmp(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::iso::iso_type::IsoType;
+
+    fn entry(
+        subtree_left: u16,
+        subtree_right: u16,
+        sector: u32,
+        size: u32,
+        attributes: DirectoryEntryAttributes,
+        name: &str,
+    ) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&subtree_left.to_le_bytes());
+        entry.extend_from_slice(&subtree_right.to_le_bytes());
+        entry.extend_from_slice(&sector.to_le_bytes());
+        entry.extend_from_slice(&size.to_le_bytes());
+        entry.push(attributes.bits());
+        entry.push(name.len() as u8);
+        entry.extend_from_slice(name.as_bytes());
+        entry.resize(entry.len().next_multiple_of(4), 0);
+        entry
+    }
+
+    fn file(subtree_left: u16, subtree_right: u16, name: &str) -> Vec<u8> {
+        entry(
+            subtree_left,
+            subtree_right,
+            0,
+            1,
+            DirectoryEntryAttributes::ARCHIVE,
+            name,
+        )
+    }
+
+    fn directory(sector: u32, name: &str) -> Vec<u8> {
+        let size = SECTOR_SIZE as u32;
+        entry(
+            0,
+            0,
+            sector,
+            size,
+            DirectoryEntryAttributes::DIRECTORY,
+            name,
+        )
+    }
+
+    /// Reads an image of `volume_sectors` sectors, with the given tables at
+    /// their sectors and the root table at sector 0.
+    fn read_root(tables: &[(u32, Vec<u8>)], volume_sectors: u64) -> Result<DirectoryTable, Error> {
+        let mut image = vec![0u8; (volume_sectors * SECTOR_SIZE) as usize];
+        for (sector, table) in tables {
+            let offset = (*sector as u64 * SECTOR_SIZE) as usize;
+            image[offset..offset + table.len()].copy_from_slice(table);
+        }
+
+        let volume = VolumeDescriptor {
+            iso_type: IsoType::Xsf,
+            root_offset: 0,
+            sector_size: SECTOR_SIZE,
+            identifier: *b"MICROSOFT*XBOX*MEDIA",
+            root_directory_sector: 0,
+            root_directory_size: SECTOR_SIZE as u32,
+            image_creation_time: FileTime(0),
+            volume_size: volume_sectors * SECTOR_SIZE,
+            volume_sectors,
+        };
+
+        DirectoryTable::read_root(Cursor::new(image), &volume)
+    }
+
+    #[test]
+    fn entries_outside_of_the_tree_are_found() {
+        // "B" is not linked from "C", as if sorted some other way
+        let mut root = file(0, 0, "C");
+        root.extend(file(0, 0, "B"));
+
+        let table = read_root(&[(0, root)], 1).unwrap();
+        assert_eq!(table.get_entry("c").unwrap().name, "C");
+        assert_eq!(table.get_entry("b").unwrap().name, "B");
+        assert!(table.get_entry("a").is_none());
+    }
+
+    #[test]
+    fn cycle_in_search_tree_is_rejected() {
+        // "A" links to "B" (at offset 16), which links back to itself
+        let mut root = file(0, 16 / 4, "A");
+        root.extend(file(16 / 4, 0, "B"));
+
+        let err = read_root(&[(0, root)], 1).err().unwrap();
+        assert!(err.to_string().contains("cycle"), "{err}");
+    }
+
+    #[test]
+    fn overlapping_tables_are_rejected() {
+        let root = directory(0, "SELF");
+
+        let err = read_root(&[(0, root)], 1).err().unwrap();
+        assert!(err.to_string().contains("overlaps"), "{err}");
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let sector_count = MAX_DIRECTORY_DEPTH as u32 + 1;
+        let tables: Vec<_> = (0..sector_count)
+            .map(|sector| (sector, directory(sector + 1, "D")))
+            .collect();
+
+        let err = read_root(&tables, sector_count as u64 + 1).err().unwrap();
+        assert!(err.to_string().contains("nested"), "{err}");
+    }
+
+    #[test]
+    fn table_outside_of_the_volume_is_rejected() {
+        let root = directory(8, "FAR");
+
+        let err = read_root(&[(0, root)], 4).err().unwrap();
+        assert!(err.to_string().contains("outside of the volume"), "{err}");
+    }
+}
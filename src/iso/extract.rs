@@ -0,0 +1,352 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error, bail};
+
+use glob::Pattern;
+
+use rayon::prelude::*;
+
+use super::*;
+
+#[derive(Clone, Debug, Default)]
+pub struct ExtractOptions {
+    /// Only files whose path matches one of these are extracted, or all files
+    /// if there are none; see [`WindowsPath::matches`].
+    pub filters: Vec<Pattern>,
+    /// Files whose path matches one of these are skipped, even if they match
+    /// a filter.
+    pub excludes: Vec<Pattern>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractResult {
+    pub file_count: u64,
+    pub total_size: u64,
+}
+
+/// Extracts the files from an ISO image into `dest_dir`, keeping the directory
+/// structure, as used by "extracted game folder" installs.
+///
+/// The source image files are re-opened for every file, so that files can be
+/// copied in parallel on the current rayon thread pool. The read-only attribute is
+/// kept on all platforms; the hidden, system and archive ones only on Windows.
+pub fn extract(
+    source_path: &Path,
+    dest_dir: &Path,
+    options: &ExtractOptions,
+) -> Result<ExtractResult, Error> {
+    let source_iso_file =
+        ImageReader::open(source_path).context("error opening source ISO file")?;
+    let source = source_iso_file.try_clone()?;
+    let source_iso = IsoReader::read(source_iso_file).context("error reading source ISO")?;
+
+    for (path, entry) in source_iso.directory_table.walk() {
+        check_file_name(&path, &entry.name)?;
+    }
+
+    let files: Vec<_> = source_iso
+        .directory_table
+        .walk()
+        .files_only()
+        .filter(|(path, _)| {
+            options.filters.is_empty() || options.filters.iter().any(|filter| path.matches(filter))
+        })
+        .filter(|(path, _)| !options.excludes.iter().any(|exclude| path.matches(exclude)))
+        .collect();
+
+    // all directories are created if there are no filters, even empty ones;
+    // otherwise only those of the matching files
+    let mut dirs: Vec<WindowsPath> = if options.filters.is_empty() {
+        source_iso
+            .directory_table
+            .walk()
+            .filter(|(_, entry)| entry.is_directory())
+            .map(|(path, _)| path)
+            .collect()
+    } else {
+        files
+            .iter()
+            .map(|(path, _)| WindowsPath {
+                components: path.components[..path.components.len() - 1].to_vec(),
+            })
+            .collect()
+    };
+    dirs.dedup_by(|a, b| a.components == b.components);
+
+    fs::create_dir_all(dest_dir).context("error creating destination folder")?;
+
+    for dir in dirs.iter() {
+        let dir_path = dest_path(dest_dir, dir);
+        fs::create_dir_all(&dir_path)
+            .with_context(|| format!("error creating folder {}", dir_path.display()))?;
+    }
+
+    let volume = &source_iso.volume_descriptor;
+
+    files.par_iter().try_for_each(|(path, entry)| {
+        let file_path = dest_path(dest_dir, path);
+
+        let mut source_iso_file = source.try_clone()?;
+        let position = volume.root_offset + (entry.sector as u64) * volume.sector_size;
+        source_iso_file.seek(SeekFrom::Start(position))?;
+
+        extract_file(source_iso_file, entry, &file_path)
+            .with_context(|| format!("error extracting {}", file_path.display()))
+    })?;
+
+    Ok(ExtractResult {
+        file_count: files.len() as u64,
+        total_size: files.iter().map(|(_, entry)| entry.size as u64).sum(),
+    })
+}
+
+/// Names come from the image, and must not escape the destination folder, be
+/// taken as a drive (`C:`) or open a device on Windows.
+fn check_file_name(path: &WindowsPath, name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\\', ':', '\0'])
+        || is_reserved_name(name)
+    {
+        bail!("invalid file name {name:?} at {path} in ISO image");
+    }
+
+    Ok(())
+}
+
+/// Windows device names, which are reserved with any extension, like `nul.txt`.
+fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    let stem = stem.to_ascii_uppercase();
+
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            (stem.starts_with("COM") || stem.starts_with("LPT"))
+                && stem.len() == 4
+                && matches!(stem.as_bytes()[3], b'1'..=b'9')
+        }
+    }
+}
+
+fn dest_path(dest_dir: &Path, path: &WindowsPath) -> PathBuf {
+    let mut dest_path = dest_dir.to_owned();
+    dest_path.extend(path.components.iter());
+    dest_path
+}
+
+fn extract_file<R: Read>(reader: R, entry: &DirectoryEntry, file_path: &Path) -> Result<(), Error> {
+    // a read-only file from a previous extraction would not be writable
+    if let Ok(metadata) = fs::metadata(file_path) {
+        let mut permissions = metadata.permissions();
+        if permissions.readonly() {
+            make_writable(&mut permissions);
+            fs::set_permissions(file_path, permissions)?;
+        }
+    }
+
+    let mut open_options = File::options();
+    open_options.write(true).create(true).truncate(true);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        // GDF attributes have the same values as the Windows ones
+        let attributes = entry.attributes
+            & (DirectoryEntryAttributes::HIDDEN
+                | DirectoryEntryAttributes::SYSTEM
+                | DirectoryEntryAttributes::ARCHIVE);
+        open_options.attributes(attributes.bits() as u32);
+    }
+
+    let mut file = open_options.open(file_path)?;
+
+    let size = io::copy(&mut reader.take(entry.size as u64), &mut file)?;
+    if size < entry.size as u64 {
+        bail!("file data is truncated");
+    }
+
+    if entry
+        .attributes
+        .contains(DirectoryEntryAttributes::READ_ONLY)
+    {
+        let mut permissions = file.metadata()?.permissions();
+        permissions.set_readonly(true);
+        file.set_permissions(permissions)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn make_writable(permissions: &mut fs::Permissions) {
+    use std::os::unix::fs::PermissionsExt;
+
+    // `set_readonly(false)` would make it writable for everyone
+    permissions.set_mode(permissions.mode() | 0o200);
+}
+
+#[cfg(not(unix))]
+fn make_writable(permissions: &mut fs::Permissions) {
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::{TempDir, tempdir};
+
+    /// Writes the files in `source_dir` as an image, next to it.
+    fn image_of(dir: &TempDir, source_dir: &Path) -> PathBuf {
+        let image_path = dir.path().join("image.iso");
+        DirectoryImage::new(source_dir)
+            .unwrap()
+            .write(File::create(&image_path).unwrap())
+            .unwrap();
+        image_path
+    }
+
+    fn game_image(dir: &TempDir) -> PathBuf {
+        let source_dir = dir.path().join("source");
+        fs::create_dir_all(source_dir.join("media").join("sub")).unwrap();
+        fs::create_dir_all(source_dir.join("cache")).unwrap();
+        fs::write(source_dir.join("default.xex"), b"XEX2").unwrap();
+        fs::write(source_dir.join("media").join("a.bin"), b"a").unwrap();
+        fs::write(source_dir.join("media").join("sub").join("b.bin"), b"b").unwrap();
+        image_of(dir, &source_dir)
+    }
+
+    fn extract_with(
+        image_path: &Path,
+        dest_dir: &Path,
+        filters: &[&str],
+        excludes: &[&str],
+    ) -> Result<ExtractResult, Error> {
+        let patterns = |globs: &[&str]| globs.iter().map(|g| Pattern::new(g).unwrap()).collect();
+        let options = ExtractOptions {
+            filters: patterns(filters),
+            excludes: patterns(excludes),
+        };
+        extract(image_path, dest_dir, &options)
+    }
+
+    #[test]
+    fn directory_structure_is_kept() {
+        let dir = tempdir().unwrap();
+        let image_path = game_image(&dir);
+        let dest_dir = dir.path().join("dest");
+
+        let result = extract_with(&image_path, &dest_dir, &[], &[]).unwrap();
+        assert_eq!(result.file_count, 3);
+        assert_eq!(result.total_size, 6);
+
+        assert_eq!(fs::read(dest_dir.join("default.xex")).unwrap(), b"XEX2");
+        assert_eq!(
+            fs::read(dest_dir.join("media").join("a.bin")).unwrap(),
+            b"a"
+        );
+        let b_path = dest_dir.join("media").join("sub").join("b.bin");
+        assert_eq!(fs::read(b_path).unwrap(), b"b");
+        assert!(dest_dir.join("cache").is_dir());
+    }
+
+    #[test]
+    fn only_matching_files_are_extracted() {
+        let dir = tempdir().unwrap();
+        let image_path = game_image(&dir);
+        let dest_dir = dir.path().join("dest");
+
+        let result = extract_with(&image_path, &dest_dir, &["MEDIA/**"], &["**/sub/*"]).unwrap();
+        assert_eq!(result.file_count, 1);
+
+        assert!(dest_dir.join("media").join("a.bin").is_file());
+        assert!(!dest_dir.join("media").join("sub").exists());
+        assert!(!dest_dir.join("default.xex").exists());
+        assert!(!dest_dir.join("cache").exists());
+    }
+
+    #[test]
+    fn excluded_files_are_skipped() {
+        let dir = tempdir().unwrap();
+        let image_path = game_image(&dir);
+        let dest_dir = dir.path().join("dest");
+
+        let result = extract_with(&image_path, &dest_dir, &[], &["*.xex"]).unwrap();
+        assert_eq!(result.file_count, 2);
+
+        assert!(!dest_dir.join("default.xex").exists());
+        assert!(dest_dir.join("media").join("sub").join("b.bin").is_file());
+    }
+
+    #[test]
+    fn read_only_attribute_is_kept() {
+        let dir = tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        fs::create_dir(&source_dir).unwrap();
+        let file_path = source_dir.join("readonly.bin");
+        fs::write(&file_path, b"data").unwrap();
+        let mut permissions = fs::metadata(&file_path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&file_path, permissions).unwrap();
+
+        let image_path = image_of(&dir, &source_dir);
+        let dest_dir = dir.path().join("dest");
+
+        // twice, as the read-only file has to be replaced the second time
+        for _ in 0..2 {
+            extract_with(&image_path, &dest_dir, &[], &[]).unwrap();
+            let metadata = fs::metadata(dest_dir.join("readonly.bin")).unwrap();
+            assert!(metadata.permissions().readonly());
+        }
+    }
+
+    #[test]
+    fn unsafe_names_are_rejected() {
+        for name in [
+            "..", "/etc", "\\etc", "C:x", "CON", "nul.txt", "com1", "a\0b",
+        ] {
+            let dir = tempdir().unwrap();
+            let source_dir = dir.path().join("source");
+            fs::create_dir(&source_dir).unwrap();
+            fs::write(source_dir.join("z".repeat(name.len())), b"data").unwrap();
+            let image_path = image_of(&dir, &source_dir);
+
+            // the only entry of the root table, with its name after 14 bytes
+            let mut image = fs::read(&image_path).unwrap();
+            let iso = IsoReader::read(io::Cursor::new(&image)).unwrap();
+            let name_offset = iso.directory_table.sector as usize * SECTOR_SIZE as usize + 14;
+            image[name_offset..name_offset + name.len()].copy_from_slice(name.as_bytes());
+            fs::write(&image_path, image).unwrap();
+
+            let dest_dir = dir.path().join("dest");
+            let err = extract_with(&image_path, &dest_dir, &[], &[]).unwrap_err();
+            assert!(
+                err.to_string().contains("invalid file name"),
+                "{name}: {err}"
+            );
+            assert!(!dest_dir.exists(), "{name}");
+        }
+    }
+
+    #[test]
+    fn ordinary_names_are_not_reserved() {
+        for name in [
+            "console.log",
+            "nullable",
+            "com10",
+            "lpt0.bin",
+            "default.xex",
+        ] {
+            assert!(!is_reserved_name(name), "{name}");
+        }
+        for name in ["AUX", "prn.dat", "LPT9", "con .txt"] {
+            assert!(is_reserved_name(name), "{name}");
+        }
+    }
+}
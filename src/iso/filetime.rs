@@ -0,0 +1,111 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 100ns intervals between 1601-01-01 and 1970-01-01
+const UNIX_EPOCH_INTERVALS: u64 = 116_444_736_000_000_000;
+
+const INTERVALS_PER_SECOND: u64 = 10_000_000;
+
+/// A Windows FILETIME: 100ns intervals since 1601-01-01 UTC.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileTime(pub u64);
+
+impl FileTime {
+    pub fn now() -> FileTime {
+        FileTime::from_system_time(SystemTime::now())
+    }
+
+    /// Times before 1970 are clamped to 1970-01-01.
+    pub fn from_system_time(time: SystemTime) -> FileTime {
+        let since_unix_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        FileTime(UNIX_EPOCH_INTERVALS + (since_unix_epoch.as_nanos() / 100) as u64)
+    }
+
+    /// `None` for times before 1970-01-01, which includes an unset (zero) time.
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        let since_unix_epoch = self.0.checked_sub(UNIX_EPOCH_INTERVALS)?;
+        UNIX_EPOCH.checked_add(Duration::from_nanos(since_unix_epoch.saturating_mul(100)))
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// Formats as `YYYY-MM-DD hh:mm:ss UTC`.
+impl fmt::Display for FileTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0 / INTERVALS_PER_SECOND;
+        let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+        // days since 0000-03-01, so that leap days come last in a year; see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = days + 584_694;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epoch_is_formatted() {
+        assert_eq!(FileTime(0).to_string(), "1601-01-01 00:00:00 UTC");
+        assert_eq!(
+            FileTime(UNIX_EPOCH_INTERVALS).to_string(),
+            "1970-01-01 00:00:00 UTC"
+        );
+    }
+
+    #[test]
+    fn leap_days_are_formatted() {
+        assert_eq!(
+            FileTime(125_963_012_960_000_000).to_string(),
+            "2000-02-29 12:34:56 UTC"
+        );
+        // 2100 is not a leap year
+        assert_eq!(
+            FileTime(157_520_160_000_000_000 - 86_400 * INTERVALS_PER_SECOND).to_string(),
+            "2100-02-28 00:00:00 UTC"
+        );
+    }
+
+    #[test]
+    fn image_timestamp_is_formatted() {
+        // as stored in a volume descriptor, with a fraction of a second
+        let time = FileTime(0x01c5_ef34_7333_3200 + 1234);
+        assert_eq!(time.to_string(), "2005-11-22 07:15:00 UTC");
+    }
+
+    #[test]
+    fn system_time_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_132_643_700);
+        let file_time = FileTime::from_system_time(time);
+        assert_eq!(file_time, FileTime(0x01c5_ef34_7333_3200));
+        assert_eq!(file_time.to_system_time(), Some(time));
+
+        assert_eq!(FileTime(0).to_system_time(), None);
+        assert!(FileTime(0).is_zero());
+    }
+}
@@ -0,0 +1,228 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::Error;
+
+use super::iso_type::IsoType;
+use super::*;
+
+/// An entry whose data goes past the end of the image.
+#[derive(Clone, Debug)]
+pub struct TruncatedEntry {
+    pub path: WindowsPath,
+    /// End of the entry's data, from the start of the game partition
+    pub end: u64,
+}
+
+/// Result of comparing where the directory entries of an image point with how
+/// long the image really is.
+#[derive(Clone, Debug)]
+pub struct ImageHealth {
+    /// Size of the game partition, as far as the image file goes
+    pub volume_size: u64,
+    /// Size of the game partition up to the end of the last entry
+    pub used_size: u64,
+    pub truncated_entries: Vec<TruncatedEntry>,
+    /// Bytes after the end of the image as it should be: past the disc size of
+    /// full disc images, or a partial sector at the end
+    pub trailing_size: u64,
+}
+
+impl ImageHealth {
+    pub fn check<R: Read + Seek>(iso: &IsoReader<R>) -> ImageHealth {
+        let volume_descriptor = &iso.volume_descriptor;
+        let volume_size = volume_descriptor.volume_size;
+
+        let truncated_entries = iso
+            .directory_table
+            .walk()
+            .map(|(path, entry)| TruncatedEntry {
+                path,
+                end: (entry.sector as u64) * SECTOR_SIZE + (entry.size as u64),
+            })
+            .filter(|entry| entry.end > volume_size)
+            .collect();
+
+        let used_size = iso.get_max_used_prefix_size();
+
+        // a partial sector at the end is only junk if no entry uses it
+        let partial_sector_size = volume_size % SECTOR_SIZE;
+        let image_size = volume_descriptor.root_offset + volume_size;
+
+        let trailing_size = match full_image_size(volume_descriptor.iso_type) {
+            Some(full_image_size) if image_size > full_image_size => image_size - full_image_size,
+            _ if used_size <= volume_size - partial_sector_size => partial_sector_size,
+            _ => 0,
+        };
+
+        ImageHealth {
+            volume_size,
+            used_size,
+            truncated_entries,
+            trailing_size,
+        }
+    }
+
+    /// Whether files or directories are cut off, so that converting the image
+    /// as it is would lose data.
+    pub fn is_truncated(&self) -> bool {
+        !self.truncated_entries.is_empty()
+    }
+
+    /// Bytes that would have to be appended for all entries to fit.
+    pub fn missing_size(&self) -> u64 {
+        self.used_size.saturating_sub(self.volume_size)
+    }
+}
+
+/// Size of a complete dump of a disc of this type, as made by Xbox Backup
+/// Creator or redump.
+fn full_image_size(iso_type: IsoType) -> Option<u64> {
+    match iso_type {
+        IsoType::Xgd1 => Some(7_825_162_240),
+        IsoType::Xgd2 => Some(7_307_001_856),
+        IsoType::Xgd3 => Some(8_738_846_720),
+        IsoType::Xsf => None,
+    }
+}
+
+/// Reads past the end of the inner reader as zeroes, up to `size`.
+pub struct ZeroPadReader<R: Read + Seek> {
+    reader: R,
+    size: u64,
+    position: u64,
+}
+
+impl<R: Read + Seek> ZeroPadReader<R> {
+    pub fn new(mut reader: R, size: u64) -> Result<ZeroPadReader<R>, Error> {
+        let position = reader.stream_position()?;
+        Ok(ZeroPadReader {
+            reader,
+            size,
+            position,
+        })
+    }
+}
+
+impl<R: Read + Seek> Read for ZeroPadReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.size.saturating_sub(self.position);
+        let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let mut read = self.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            buf[..len].fill(0);
+            read = len;
+        }
+
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for ZeroPadReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        self.reader.seek(SeekFrom::Start(self.position))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::io::Cursor;
+
+    use tempfile::tempdir;
+
+    /// An image with a single file, whose data is at the end of it.
+    fn image() -> Vec<u8> {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("data.bin"), vec![1; 0x3000]).unwrap();
+
+        let mut image = Vec::new();
+        DirectoryImage::new(dir.path())
+            .unwrap()
+            .write(&mut image)
+            .unwrap();
+        image
+    }
+
+    fn check(image: Vec<u8>) -> ImageHealth {
+        ImageHealth::check(&IsoReader::read(Cursor::new(image)).unwrap())
+    }
+
+    #[test]
+    fn complete_image_is_healthy() {
+        let image = image();
+        let image_size = image.len() as u64;
+
+        let health = check(image);
+        assert!(!health.is_truncated());
+        assert_eq!(health.volume_size, image_size);
+        assert_eq!(health.used_size, image_size);
+        assert_eq!(health.missing_size(), 0);
+        assert_eq!(health.trailing_size, 0);
+    }
+
+    #[test]
+    fn truncated_entries_are_found() {
+        let mut image = image();
+        let image_size = image.len() as u64;
+        image.truncate(image.len() - 0x1800);
+
+        let health = check(image);
+        assert!(health.is_truncated());
+        assert_eq!(health.truncated_entries.len(), 1);
+        assert_eq!(health.truncated_entries[0].path.to_string(), "\\data.bin");
+        assert_eq!(health.truncated_entries[0].end, image_size);
+        assert_eq!(health.missing_size(), 0x1800);
+        assert_eq!(health.trailing_size, 0);
+    }
+
+    #[test]
+    fn partial_sector_at_the_end_is_trailing() {
+        let mut image = image();
+        image.extend_from_slice(&[0xff; 100]);
+
+        let health = check(image);
+        assert!(!health.is_truncated());
+        assert_eq!(health.trailing_size, 100);
+    }
+
+    #[test]
+    fn zero_pad_reader_reads_zeroes_past_the_end() {
+        let mut inner = Cursor::new(vec![1, 2, 3, 4, 5]);
+        inner.seek(SeekFrom::Start(1)).unwrap();
+
+        // starts where the inner reader is
+        let mut reader = ZeroPadReader::new(inner, 8).unwrap();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, [2, 3, 4, 5, 0, 0, 0]);
+
+        let mut buf = [0xff; 4];
+        reader.seek(SeekFrom::Start(6)).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf[..2], [0, 0]);
+
+        reader.seek(SeekFrom::End(4)).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+}
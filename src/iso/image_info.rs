@@ -0,0 +1,325 @@
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::PoisonError;
+
+use byteorder::{BE, ByteOrder};
+
+use anyhow::{Context, Error, bail};
+
+use super::iso_type::IsoType;
+use super::*;
+
+const METADATA_SIZE: usize = 0x800;
+
+/// Physical sector number of the first data sector on a DVD
+const DATA_START_PSN: u32 = 0x030000;
+
+/// Sidecar file names for the security sector, PFI and DMI dumps, after the
+/// image name without its extension: Xbox Backup Creator, redumper and
+/// DiscImageCreator style.
+const SS_SUFFIXES: &[&str] = &[".ss", ".security", "_SS.bin"];
+const PFI_SUFFIXES: &[&str] = &[".pfi", ".physical", "_PFI.bin"];
+const DMI_SUFFIXES: &[&str] = &[".dmi", ".manufacturer", "_DMI.bin"];
+
+/// The layout of an Xbox disc image: where its partitions are, and how much
+/// of it is actually used.
+#[derive(Clone, Debug)]
+pub struct XgdImageInfo {
+    pub iso_type: IsoType,
+    /// Size of the whole image
+    pub image_size: u64,
+    /// Size of the video partition, which comes before the game partition on
+    /// full disc images; 0 for game-partition-only (XSF) images
+    pub video_partition_size: u64,
+    /// Size of the second layer's video data, which comes after the game
+    /// partition on full disc images
+    pub video_layer1_size: u64,
+    /// Whether the video partition has a filesystem in it, rather than being
+    /// zeroed out
+    pub has_video_data: bool,
+    pub game_partition_offset: u64,
+    pub game_partition_size: u64,
+    /// Size of the game partition up to the end of the last file, which is
+    /// what trimming from the end keeps
+    pub game_partition_used_size: u64,
+    /// First sector of the second layer, when burned to a dual layer disc
+    pub layer_break: Option<u64>,
+    pub pfi: Option<PhysicalFormatInfo>,
+    /// Disc manufacturing information, raw
+    pub dmi: Option<Vec<u8>>,
+    /// Security sector, raw
+    pub security_sector: Option<Vec<u8>>,
+}
+
+/// The physical format information (PFI) of a DVD, as defined in ECMA-267.
+#[derive(Clone, Debug)]
+pub struct PhysicalFormatInfo {
+    pub book_type: u8,
+    pub part_version: u8,
+    pub layer_count: u8,
+    /// Opposite track path: the second layer is read from the outside in
+    pub opposite_track_path: bool,
+    pub data_start_psn: u32,
+    pub data_end_psn: u32,
+    pub layer0_end_psn: u32,
+    pub raw: Vec<u8>,
+}
+
+impl XgdImageInfo {
+    pub fn read<R: Read + Seek>(iso: &IsoReader<R>) -> Result<XgdImageInfo, Error> {
+        let volume_descriptor = &iso.volume_descriptor;
+        let iso_type = volume_descriptor.iso_type;
+
+        let mut reader = iso.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        let image_size = reader.seek(SeekFrom::End(0))?;
+
+        // the video partition is an ordinary DVD filesystem, with an ISO 9660
+        // volume descriptor at sector 16
+        let has_video_data = iso_type != IsoType::Xsf && {
+            let mut buf = [0u8; 6];
+            reader.seek(SeekFrom::Start(16 * SECTOR_SIZE))?;
+            reader.read_exact(&mut buf)?;
+            &buf[1..6] == b"CD001"
+        };
+
+        drop(reader);
+
+        // the volume runs to the end of the image, which on full disc images
+        // includes the second layer's video data
+        let game_partition_size = iso_type
+            .game_partition_size()
+            .map_or(volume_descriptor.volume_size, |size| {
+                size.min(volume_descriptor.volume_size)
+            });
+
+        let layer_break = match iso_type {
+            IsoType::Xgd1 => Some(1913776),
+            IsoType::Xgd2 => Some(1913760),
+            IsoType::Xgd3 => Some(2133520),
+            IsoType::Xsf => None,
+        };
+
+        Ok(XgdImageInfo {
+            iso_type,
+            image_size,
+            video_partition_size: volume_descriptor.root_offset,
+            video_layer1_size: volume_descriptor.volume_size - game_partition_size,
+            has_video_data,
+            game_partition_offset: volume_descriptor.root_offset,
+            game_partition_size,
+            game_partition_used_size: iso.get_max_used_prefix_size(),
+            layer_break,
+            pfi: None,
+            dmi: None,
+            security_sector: None,
+        })
+    }
+
+    /// Reads the security sector, PFI and DMI from the files dumping tools
+    /// store next to the image, named after it. Missing files are skipped.
+    ///
+    /// The layer break is taken from the PFI, if there is one.
+    pub fn read_metadata_files(&mut self, image_path: &Path) -> Result<(), Error> {
+        self.security_sector = read_metadata_file(image_path, SS_SUFFIXES)?;
+
+        self.pfi = read_metadata_file(image_path, PFI_SUFFIXES)?
+            .map(PhysicalFormatInfo::parse)
+            .transpose()?;
+
+        self.dmi = read_metadata_file(image_path, DMI_SUFFIXES)?;
+
+        if let Some(pfi) = &self.pfi
+            && pfi.layer_count == 2
+        {
+            self.layer_break = Some(pfi.layer0_sector_count());
+        }
+
+        Ok(())
+    }
+
+    /// Whether this is a full disc image, rather than only the game partition.
+    pub fn is_full_image(&self) -> bool {
+        self.iso_type != IsoType::Xsf
+    }
+
+    /// Bytes saved by trimming unused space from the end of the game partition.
+    pub fn trim_savings(&self) -> u64 {
+        self.game_partition_size
+            .saturating_sub(self.game_partition_used_size)
+    }
+}
+
+impl PhysicalFormatInfo {
+    pub fn parse(raw: Vec<u8>) -> Result<PhysicalFormatInfo, Error> {
+        if raw.len() < 16 {
+            bail!("PFI is too short: {} bytes", raw.len());
+        }
+
+        let data_start_psn = BE::read_u32(&raw[4..8]);
+        if data_start_psn != DATA_START_PSN {
+            bail!("unexpected PFI data start sector {data_start_psn:#08x}");
+        }
+
+        Ok(PhysicalFormatInfo {
+            book_type: raw[0] >> 4,
+            part_version: raw[0] & 0x0f,
+            layer_count: ((raw[2] >> 5) & 0x03) + 1,
+            opposite_track_path: raw[2] & 0x10 != 0,
+            data_start_psn,
+            data_end_psn: BE::read_u32(&raw[8..12]),
+            layer0_end_psn: BE::read_u32(&raw[12..16]),
+            raw,
+        })
+    }
+
+    pub fn layer0_sector_count(&self) -> u64 {
+        (self.layer0_end_psn.saturating_sub(self.data_start_psn) as u64) + 1
+    }
+
+    /// Number of data sectors on all layers.
+    pub fn sector_count(&self) -> u64 {
+        if self.layer_count == 1 {
+            return (self.data_end_psn.saturating_sub(self.data_start_psn) as u64) + 1;
+        }
+
+        // on the second layer, sector numbers continue from the bitwise
+        // complement of the last one on the first layer
+        let layer1_start_psn = !self.layer0_end_psn & 0x00ff_ffff;
+        let layer1_sector_count = (self.data_end_psn.saturating_sub(layer1_start_psn) as u64) + 1;
+
+        self.layer0_sector_count() + layer1_sector_count
+    }
+}
+
+fn read_metadata_file(image_path: &Path, suffixes: &[&str]) -> Result<Option<Vec<u8>>, Error> {
+    let Some(stem) = image_path.file_stem().and_then(|stem| stem.to_str()) else {
+        return Ok(None);
+    };
+
+    for suffix in suffixes {
+        let path = image_path.with_file_name(format!("{stem}{suffix}"));
+        if !path.is_file() {
+            continue;
+        }
+
+        let data = fs::read(&path).with_context(|| format!("error reading {}", path.display()))?;
+        if data.len() != METADATA_SIZE {
+            bail!(
+                "{} has unexpected size {} (expected {METADATA_SIZE})",
+                path.display(),
+                data.len()
+            );
+        }
+
+        return Ok(Some(data));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    /// A PFI with the given layer type byte and sector numbers, as in the
+    /// first 16 bytes of a disc's PFI; the rest is zeroes.
+    fn pfi(layers: u8, data_end_psn: u32, layer0_end_psn: u32) -> Vec<u8> {
+        let mut raw = vec![0u8; METADATA_SIZE];
+        raw[..4].copy_from_slice(&[0x01, 0x0f, layers, 0x10]);
+        BE::write_u32(&mut raw[4..8], DATA_START_PSN);
+        BE::write_u32(&mut raw[8..12], data_end_psn);
+        BE::write_u32(&mut raw[12..16], layer0_end_psn);
+        raw
+    }
+
+    /// Dual layer, opposite track path
+    const DUAL_LAYER: u8 = 0x31;
+
+    fn xgd2_pfi() -> Vec<u8> {
+        pfi(DUAL_LAYER, 0xfcffdf, 0x20339f)
+    }
+
+    #[test]
+    fn xgd2_pfi_is_parsed() {
+        let pfi = PhysicalFormatInfo::parse(xgd2_pfi()).unwrap();
+        assert_eq!(pfi.book_type, 0);
+        assert_eq!(pfi.part_version, 1);
+        assert_eq!(pfi.layer_count, 2);
+        assert!(pfi.opposite_track_path);
+        assert_eq!(pfi.layer0_sector_count(), 1913760);
+        assert_eq!(pfi.sector_count(), 3827488);
+        assert_eq!(pfi.sector_count() * SECTOR_SIZE, 7838695424);
+    }
+
+    #[test]
+    fn xgd3_pfi_is_parsed() {
+        let pfi = PhysicalFormatInfo::parse(pfi(DUAL_LAYER, 0xfcffe6, 0x238e0f)).unwrap();
+        assert_eq!(pfi.layer0_sector_count(), 2133520);
+        assert_eq!(pfi.sector_count(), 4267015);
+    }
+
+    #[test]
+    fn single_layer_pfi_is_parsed() {
+        let pfi = PhysicalFormatInfo::parse(pfi(0x01, 0x0fffff, 0)).unwrap();
+        assert_eq!(pfi.layer_count, 1);
+        assert!(!pfi.opposite_track_path);
+        assert_eq!(pfi.sector_count(), 0x0fffff - 0x030000 + 1);
+    }
+
+    #[test]
+    fn malformed_pfi_is_an_error() {
+        assert!(PhysicalFormatInfo::parse(vec![0; 15]).is_err());
+
+        let mut raw = xgd2_pfi();
+        BE::write_u32(&mut raw[4..8], 0);
+        assert!(PhysicalFormatInfo::parse(raw).is_err());
+    }
+
+    fn image_info() -> XgdImageInfo {
+        XgdImageInfo {
+            iso_type: IsoType::Xgd2,
+            image_size: 7838695424,
+            video_partition_size: IsoType::Xgd2.root_offset(),
+            video_layer1_size: 0,
+            has_video_data: false,
+            game_partition_offset: IsoType::Xgd2.root_offset(),
+            game_partition_size: 0x1b3880000,
+            game_partition_used_size: 0x1b3880000,
+            layer_break: None,
+            pfi: None,
+            dmi: None,
+            security_sector: None,
+        }
+    }
+
+    #[test]
+    fn metadata_files_are_read() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("game.pfi"), xgd2_pfi()).unwrap();
+        fs::write(dir.path().join("game_SS.bin"), [0xaa; METADATA_SIZE]).unwrap();
+
+        let mut info = image_info();
+        info.read_metadata_files(&dir.path().join("game.iso"))
+            .unwrap();
+
+        assert_eq!(info.layer_break, Some(1913760));
+        assert_eq!(info.pfi.unwrap().raw, xgd2_pfi());
+        assert_eq!(info.security_sector.unwrap(), [0xaa; METADATA_SIZE]);
+        assert!(info.dmi.is_none());
+    }
+
+    #[test]
+    fn metadata_file_of_wrong_size_is_an_error() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("game.dmi"), [0; 16]).unwrap();
+
+        let mut info = image_info();
+        assert!(
+            info.read_metadata_files(&dir.path().join("game.iso"))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{Context, Error};
+
+use crate::god::{ConHeader, GodReader};
+
+use super::*;
+
+/// Reads an image file the way it is stored: plain (maybe split, see
+/// [`SplitReader`]), compressed (see [`CompressedReader`]) or converted to a
+/// GOD package (see [`GodReader`]), detected by its magic bytes rather than
+/// its file extension.
+///
+/// A GOD package is opened by its CON header file, and read as the game
+/// partition only.
+pub enum ImageReader {
+    Plain(SplitReader),
+    Compressed(CompressedReader<SplitReader>),
+    God(GodReader),
+}
+
+impl ImageReader {
+    pub fn open(path: &Path) -> Result<ImageReader, Error> {
+        let mut reader = SplitReader::open(path)?;
+
+        if ConHeader::detect(&mut reader)? {
+            return Ok(ImageReader::God(
+                GodReader::open_con_header(path).context("error reading GOD package")?,
+            ));
+        }
+
+        Ok(match CompressedFormat::detect(&mut reader)? {
+            Some(format) => ImageReader::Compressed(
+                CompressedReader::new(reader)
+                    .with_context(|| format!("error reading {format} image"))?,
+            ),
+            None => {
+                reader.rewind()?;
+                ImageReader::Plain(reader)
+            }
+        })
+    }
+
+    /// Another reader over the same image, with its own position.
+    pub fn try_clone(&self) -> Result<ImageReader, Error> {
+        Ok(match self {
+            ImageReader::Plain(reader) => ImageReader::Plain(reader.try_clone()?),
+            ImageReader::Compressed(reader) => {
+                ImageReader::Compressed(reader.with_reader(reader.get_ref().try_clone()?))
+            }
+            ImageReader::God(reader) => ImageReader::God(reader.try_clone()?),
+        })
+    }
+
+    pub fn compressed_format(&self) -> Option<CompressedFormat> {
+        match self {
+            ImageReader::Plain(_) | ImageReader::God(_) => None,
+            ImageReader::Compressed(reader) => Some(reader.format),
+        }
+    }
+
+    /// Size of the (uncompressed) image.
+    pub fn size(&self) -> u64 {
+        match self {
+            ImageReader::Plain(reader) => reader.size(),
+            ImageReader::Compressed(reader) => reader.size(),
+            ImageReader::God(reader) => reader.size(),
+        }
+    }
+}
+
+impl Read for ImageReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ImageReader::Plain(reader) => reader.read(buf),
+            ImageReader::Compressed(reader) => reader.read(buf),
+            ImageReader::God(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for ImageReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            ImageReader::Plain(reader) => reader.seek(pos),
+            ImageReader::Compressed(reader) => reader.seek(pos),
+            ImageReader::God(reader) => reader.seek(pos),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Mutex, PoisonError};
+
+use super::*;
+
+/// A file in an ISO image, that can only be read and seeked within its own
+/// bounds. Positions are relative to the start of the file.
+///
+/// All files opened from the same [`IsoReader`] share its underlying reader,
+/// seeking it before every read, so several of them can be used at once.
+pub struct IsoFile<'a, R: Read + Seek> {
+    reader: &'a Mutex<R>,
+    pub entry: &'a DirectoryEntry,
+    offset: u64,
+    position: u64,
+}
+
+impl<'a, R: Read + Seek> IsoFile<'a, R> {
+    pub(super) fn new(reader: &'a Mutex<R>, entry: &'a DirectoryEntry, offset: u64) -> Self {
+        IsoFile {
+            reader,
+            entry,
+            offset,
+            position: 0,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.entry.size as u64
+    }
+}
+
+impl<R: Read + Seek> Read for IsoFile<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.size().saturating_sub(self.position);
+        let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        // the reader is always seeked before use, so a panic in another file
+        // can not leave it in a bad state
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+
+        reader.seek(SeekFrom::Start(self.offset + self.position))?;
+        let len = reader.read(&mut buf[..len])?;
+
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for IsoFile<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.position)
+    }
+}
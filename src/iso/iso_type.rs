@@ -4,7 +4,7 @@ use anyhow::Error;
 
 use super::*;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum IsoType {
     Xgd3,
     Xgd2,
@@ -22,6 +22,18 @@ impl IsoType {
         }
     }
 
+    /// Size of the game partition on a pressed disc; on full disc images it is
+    /// followed by the second layer's video data. `None` for XSF images,
+    /// which are the game partition alone.
+    pub fn game_partition_size(&self) -> Option<u64> {
+        match self {
+            IsoType::Xgd3 => Some(0x204510000),
+            IsoType::Xgd2 => Some(0x1b3880000),
+            IsoType::Xgd1 => Some(0x1a2db0000),
+            IsoType::Xsf => None,
+        }
+    }
+
     pub fn read<R: Read + Seek>(mut reader: R) -> Result<Option<IsoType>, Error> {
         if Self::check(&mut reader, IsoType::Xsf)? {
             return Ok(Some(IsoType::Xsf));
@@ -0,0 +1,148 @@
+use anyhow::{Error, bail};
+
+/// Decompresses a raw LZ4 block (no frame) into `output`, which has to be
+/// exactly the size of the uncompressed data.
+///
+/// Decoding stops as soon as `output` is full, so padding after the end of the
+/// block in `input` is ignored.
+///
+/// Based on https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md
+pub fn decompress_block(input: &[u8], output: &mut [u8]) -> Result<(), Error> {
+    let mut input = Input(input);
+    let mut position = 0;
+
+    while position < output.len() {
+        let token = input.read_u8()?;
+
+        let literals_len = input.read_len((token >> 4) as usize)?;
+        let literals = input.read_slice(literals_len)?;
+
+        let Some(dest) = output.get_mut(position..position + literals_len) else {
+            bail!("LZ4 block decompresses to more than {} bytes", output.len());
+        };
+        dest.copy_from_slice(literals);
+        position += literals_len;
+
+        if position == output.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([input.read_u8()?, input.read_u8()?]) as usize;
+        if offset == 0 || offset > position {
+            bail!("invalid LZ4 match offset {offset} at position {position}");
+        }
+
+        let match_len = input.read_len((token & 0x0f) as usize)? + 4;
+        if position + match_len > output.len() {
+            bail!("LZ4 block decompresses to more than {} bytes", output.len());
+        }
+
+        // matches can overlap with their own output, so this is byte by byte
+        for i in position..position + match_len {
+            output[i] = output[i - offset];
+        }
+        position += match_len;
+    }
+
+    Ok(())
+}
+
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_slice(1)?[0])
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.0.len() {
+            bail!("LZ4 block is truncated");
+        }
+
+        let (slice, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(slice)
+    }
+
+    /// Lengths of 15 are continued in the following bytes.
+    fn read_len(&mut self, len: usize) -> Result<usize, Error> {
+        let mut len = len;
+
+        if len == 0x0f {
+            loop {
+                let byte = self.read_u8()?;
+                len += byte as usize;
+                if byte != 0xff {
+                    break;
+                }
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decompress(input: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+        let mut output = vec![0u8; len];
+        decompress_block(input, &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn overlapping_match_repeats_its_output() {
+        // "ab", then 6 bytes from 2 back
+        let output = decompress(&[0x22, b'a', b'b', 2, 0], 8).unwrap();
+        assert_eq!(output, b"abababab");
+
+        // "x", then 10 bytes from 1 back
+        let output = decompress(&[0x16, b'x', 1, 0], 11).unwrap();
+        assert_eq!(output, [b'x'; 11]);
+    }
+
+    #[test]
+    fn lengths_of_15_are_continued() {
+        // 15 literals with a length byte of 0, and a match of 15 + 4
+        let mut input = vec![0xff, 0x00];
+        input.extend_from_slice(b"0123456789abcde");
+        input.extend_from_slice(&[15, 0, 0x00]);
+
+        let output = decompress(&input, 34).unwrap();
+        assert_eq!(&output[..15], b"0123456789abcde");
+        assert_eq!(&output[15..30], b"0123456789abcde");
+        assert_eq!(&output[30..], b"0123");
+    }
+
+    #[test]
+    fn lengths_continue_past_255() {
+        // 15 + 255 + 3 literals, then a match of 15 + 255 + 0 + 4
+        let literals: Vec<u8> = (0..273).map(|i| i as u8).collect();
+        let mut input = vec![0xff, 0xff, 0x03];
+        input.extend_from_slice(&literals);
+        input.extend_from_slice(&[1, 0, 0xff, 0x00]);
+
+        let output = decompress(&input, 273 + 274).unwrap();
+        assert_eq!(&output[..273], &literals[..]);
+        assert!(output[273..].iter().all(|&b| b == literals[272]));
+    }
+
+    #[test]
+    fn padding_after_the_block_is_ignored() {
+        let output = decompress(&[0x30, b'a', b'b', b'c', 0, 0, 0], 3).unwrap();
+        assert_eq!(output, b"abc");
+    }
+
+    #[test]
+    fn malformed_blocks_are_errors() {
+        // offset 0, and an offset before the start of the output
+        assert!(decompress(&[0x10, b'a', 0, 0], 8).is_err());
+        assert!(decompress(&[0x10, b'a', 2, 0], 8).is_err());
+        // more literals than the input has
+        assert!(decompress(&[0x40, b'a', b'b'], 8).is_err());
+        // a match past the end of the output
+        assert!(decompress(&[0x1f, b'a', 1, 0, 0x10], 8).is_err());
+    }
+}
@@ -1,20 +1,45 @@
+use std::fmt;
 use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Mutex, PoisonError};
 
 use anyhow::Error;
 
+use glob::{MatchOptions, Pattern};
+
+pub mod compressed_reader;
 pub mod directory_table;
+pub mod extract;
+pub mod filetime;
+pub mod health;
+pub mod image_info;
+pub mod image_reader;
+pub mod iso_file;
 pub mod iso_type;
+mod lz4;
+pub mod rebuild;
+pub mod split_reader;
 pub mod volume_descriptor;
+pub mod writer;
 
+pub use compressed_reader::*;
 pub use directory_table::*;
+pub use extract::*;
+pub use filetime::*;
+pub use health::*;
+pub use image_info::*;
+pub use image_reader::*;
+pub use iso_file::*;
+pub use rebuild::*;
+pub use split_reader::*;
 pub use volume_descriptor::*;
+pub use writer::*;
 
 pub const SECTOR_SIZE: u64 = 0x800;
 
 pub struct IsoReader<R: Read + Seek> {
     pub volume_descriptor: VolumeDescriptor,
     pub directory_table: DirectoryTable,
-    reader: R,
+    reader: Mutex<R>,
 }
 
 impl<R: Read + Seek> IsoReader<R> {
@@ -25,17 +50,21 @@ impl<R: Read + Seek> IsoReader<R> {
         Ok(IsoReader {
             volume_descriptor,
             directory_table,
-            reader,
+            reader: Mutex::new(reader),
         })
     }
 
     pub fn get_root(&mut self) -> Result<&mut R, Error> {
-        self.reader
-            .seek(SeekFrom::Start(self.volume_descriptor.root_offset))?;
-        Ok(&mut self.reader)
+        let reader = self
+            .reader
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        reader.seek(SeekFrom::Start(self.volume_descriptor.root_offset))?;
+        Ok(reader)
     }
 
-    pub fn get_entry(&mut self, path: &WindowsPath) -> Result<Option<&mut R>, Error> {
+    /// Opens a file (or the raw table of a directory) by its path in the image.
+    pub fn get_entry(&self, path: &WindowsPath) -> Result<Option<IsoFile<'_, R>>, Error> {
         let mut entry: Option<&DirectoryEntry> = None;
         let mut dir = Some(&self.directory_table);
 
@@ -44,33 +73,20 @@ impl<R: Read + Seek> IsoReader<R> {
             dir = entry.and_then(|entry| entry.subdirectory.as_ref());
         }
 
-        if let Some(entry) = entry {
-            let position = self.volume_descriptor.root_offset
+        Ok(entry.map(|entry| {
+            let offset = self.volume_descriptor.root_offset
                 + (entry.sector as u64) * self.volume_descriptor.sector_size;
 
-            self.reader.seek(SeekFrom::Start(position))?;
-
-            Ok(Some(&mut self.reader))
-        } else {
-            Ok(None)
-        }
+            IsoFile::new(&self.reader, entry, offset)
+        }))
     }
 
     pub fn get_max_used_prefix_size(&self) -> u64 {
-        return rec(&self.directory_table);
-        fn rec(dir: &DirectoryTable) -> u64 {
-            dir.entries
-                .iter()
-                .map(|entry| {
-                    let mut v = (entry.sector as u64) * SECTOR_SIZE + (entry.size as u64);
-                    if let Some(subdir) = &entry.subdirectory {
-                        v = v.max(rec(subdir));
-                    }
-                    v
-                })
-                .max()
-                .unwrap_or(0)
-        }
+        self.directory_table
+            .walk()
+            .map(|(_, entry)| (entry.sector as u64) * SECTOR_SIZE + (entry.size as u64))
+            .max()
+            .unwrap_or(0)
     }
 }
 
@@ -79,6 +95,36 @@ pub struct WindowsPath {
     pub components: Vec<String>,
 }
 
+impl WindowsPath {
+    pub fn join(&self, name: &str) -> WindowsPath {
+        let mut components = self.components.clone();
+        components.push(name.to_owned());
+        WindowsPath { components }
+    }
+
+    /// Matches the path against a glob like `media/*.xex` or `**/*.xex`, with
+    /// `/` as separator (not `\`) and ignoring case. Wildcards do not match
+    /// across separators.
+    pub fn matches(&self, pattern: &Pattern) -> bool {
+        let options = MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+
+        pattern.matches_with(&self.components.join("/"), options)
+    }
+}
+
+impl fmt::Display for WindowsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for component in self.components.iter() {
+            write!(f, "\\{component}")?;
+        }
+        Ok(())
+    }
+}
+
 /// Case-insensitive (ascii case, for simplicity). Uses `\` as separator.
 impl<'a, S: Into<&'a str>> From<S> for WindowsPath {
     fn from(path: S) -> WindowsPath {
@@ -0,0 +1,441 @@
+use byteorder::{LE, WriteBytesExt};
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use anyhow::{Error, bail};
+
+use crate::god::GDF_SECTOR;
+
+use super::*;
+
+const DIRECTORY_ENTRY_HEADER_SIZE: usize = 14;
+const FIRST_FREE_SECTOR: u64 = 0x21;
+
+/// A compact re-layout of an ISO image: directory tables and file data packed
+/// contiguously, starting with an XSF-style (root offset 0) volume descriptor.
+///
+/// The image itself is never materialized; file data is read from the source
+/// image on demand through [`RebuiltImageReader`].
+pub struct RebuiltImage {
+    layout: ImageLayout<u64>,
+    pub size: u64,
+}
+
+impl RebuiltImage {
+    pub fn new<R: Read + Seek>(iso: &IsoReader<R>) -> Result<RebuiltImage, Error> {
+        let vd = &iso.volume_descriptor;
+        let root = source_entries(&iso.directory_table, vd);
+        let layout = ImageLayout::new(root, vd.image_creation_time)?;
+
+        Ok(RebuiltImage {
+            size: layout.size,
+            layout,
+        })
+    }
+
+    pub fn reader<R: Read + Seek>(&self, source: R) -> RebuiltImageReader<'_, R> {
+        RebuiltImageReader {
+            image: self,
+            source,
+            position: 0,
+        }
+    }
+}
+
+/// File data is at the given position in the source image.
+fn source_entries(dir: &DirectoryTable, vd: &VolumeDescriptor) -> Vec<LayoutEntry<u64>> {
+    dir.entries
+        .iter()
+        .map(|entry| LayoutEntry {
+            name: entry.name.clone(),
+            attributes: entry.attributes,
+            content: match &entry.subdirectory {
+                Some(subdir) => LayoutContent::Directory(source_entries(subdir, vd)),
+                None => LayoutContent::File(
+                    entry.size,
+                    vd.root_offset + (entry.sector as u64) * vd.sector_size,
+                ),
+            },
+        })
+        .collect()
+}
+
+/// A file or directory to be placed into a new image.
+pub(super) struct LayoutEntry<S> {
+    pub name: String,
+    pub attributes: DirectoryEntryAttributes,
+    pub content: LayoutContent<S>,
+}
+
+pub(super) enum LayoutContent<S> {
+    Directory(Vec<LayoutEntry<S>>),
+    /// File data of the given size, from a source of type `S`
+    File(u32, S),
+}
+
+/// Directory tables and file data packed contiguously, starting with an
+/// XSF-style (root offset 0) volume descriptor. Shared by [`RebuiltImage`] and
+/// [`DirectoryImage`], which only differ in where the file data comes from.
+pub(super) struct ImageLayout<S> {
+    /// Sorted by offset, not overlapping; gaps are zeroes
+    pub extents: Vec<Extent<S>>,
+    pub size: u64,
+}
+
+pub(super) struct Extent<S> {
+    pub offset: u64,
+    pub len: u64,
+    pub data: ExtentData<S>,
+}
+
+pub(super) enum ExtentData<S> {
+    Bytes(Vec<u8>),
+    Source(S),
+}
+
+struct NewEntry {
+    name: String,
+    attributes: DirectoryEntryAttributes,
+    sector: u32,
+    size: u32,
+}
+
+impl<S> ImageLayout<S> {
+    pub fn new(
+        root: Vec<LayoutEntry<S>>,
+        image_creation_time: FileTime,
+    ) -> Result<ImageLayout<S>, Error> {
+        let mut layout = ImageLayout {
+            extents: Vec::new(),
+            size: 0,
+        };
+
+        let mut next_sector = FIRST_FREE_SECTOR;
+
+        let root_table_size = directory_table_size(&root);
+        let root_sector = next_sector;
+        next_sector += root_table_size.div_ceil(SECTOR_SIZE);
+
+        layout.add_bytes(0x10 * SECTOR_SIZE, GDF_SECTOR.to_vec());
+        layout.add_bytes(
+            0x20 * SECTOR_SIZE,
+            volume_descriptor_sector(
+                root_sector as u32,
+                root_table_size as u32,
+                image_creation_time,
+            )?,
+        );
+
+        layout.add_directory_table(root, root_sector, &mut next_sector)?;
+
+        if next_sector > u32::MAX as u64 {
+            bail!("image is too large: {next_sector} sectors");
+        }
+
+        layout.extents.sort_by_key(|e| e.offset);
+        layout.size = next_sector * SECTOR_SIZE;
+
+        Ok(layout)
+    }
+
+    fn add_bytes(&mut self, offset: u64, data: Vec<u8>) {
+        self.extents.push(Extent {
+            offset,
+            len: data.len() as u64,
+            data: ExtentData::Bytes(data),
+        });
+    }
+
+    fn add_directory_table(
+        &mut self,
+        entries: Vec<LayoutEntry<S>>,
+        sector: u64,
+        next_sector: &mut u64,
+    ) -> Result<(), Error> {
+        let mut new_entries = Vec::with_capacity(entries.len());
+
+        // laid out depth-first: each directory table is followed by
+        // everything that is inside of it
+        for entry in entries {
+            let entry_sector = *next_sector;
+
+            let size = match entry.content {
+                LayoutContent::Directory(subdir) => {
+                    let size = directory_table_size(&subdir);
+                    *next_sector += size.div_ceil(SECTOR_SIZE);
+                    self.add_directory_table(subdir, entry_sector, next_sector)?;
+                    size as u32
+                }
+                LayoutContent::File(size, source) => {
+                    *next_sector += (size as u64).div_ceil(SECTOR_SIZE);
+
+                    self.extents.push(Extent {
+                        offset: entry_sector * SECTOR_SIZE,
+                        len: size as u64,
+                        data: ExtentData::Source(source),
+                    });
+
+                    size
+                }
+            };
+
+            new_entries.push(NewEntry {
+                name: entry.name,
+                attributes: entry.attributes,
+                sector: entry_sector as u32,
+                size,
+            });
+        }
+
+        self.add_bytes(
+            sector * SECTOR_SIZE,
+            encode_directory_table(&mut new_entries)?,
+        );
+
+        Ok(())
+    }
+}
+
+fn directory_table_size<S>(entries: &[LayoutEntry<S>]) -> u64 {
+    let mut names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+    names.sort_by_key(|name| name.to_ascii_uppercase());
+    let (_, size) = layout_directory_table(&names);
+    size
+}
+
+/// Places entries in binary tree pre-order, so that the root of the tree is
+/// the first entry in the table. Returns entry offsets in sorted order, and
+/// the total table size.
+fn layout_directory_table(sorted_names: &[&str]) -> (Vec<usize>, u64) {
+    let mut order = Vec::with_capacity(sorted_names.len());
+    pre_order(0, sorted_names.len(), &mut order);
+
+    let mut offsets = vec![0; sorted_names.len()];
+    let mut offset = 0;
+
+    for index in order {
+        let len = (DIRECTORY_ENTRY_HEADER_SIZE + sorted_names[index].len()).next_multiple_of(4);
+
+        // entries can not cross sector boundaries; a completely filled sector
+        // would also have no 0xff terminator, so that is avoided as well
+        let sector_size = SECTOR_SIZE as usize;
+        if offset % sector_size + len >= sector_size {
+            offset = offset.next_multiple_of(sector_size);
+        }
+
+        offsets[index] = offset;
+        offset += len;
+    }
+
+    let size = (offset as u64)
+        .next_multiple_of(SECTOR_SIZE)
+        .max(SECTOR_SIZE);
+    (offsets, size)
+}
+
+fn pre_order(start: usize, end: usize, order: &mut Vec<usize>) {
+    if start < end {
+        let mid = start + (end - start) / 2;
+        order.push(mid);
+        pre_order(start, mid, order);
+        pre_order(mid + 1, end, order);
+    }
+}
+
+fn encode_directory_table(entries: &mut [NewEntry]) -> Result<Vec<u8>, Error> {
+    entries.sort_by_key(|e| e.name.to_ascii_uppercase());
+
+    let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+    let (offsets, size) = layout_directory_table(&names);
+
+    // subtree links are 16 bits, in 4-byte units
+    if offsets.iter().any(|&offset| offset / 4 > u16::MAX as usize) {
+        bail!("directory table is too large: {size} bytes");
+    }
+
+    let mut buffer = vec![0xff_u8; size as usize];
+
+    let children = subtree_offsets(&offsets);
+
+    for (index, new_entry) in entries.iter().enumerate() {
+        let (subtree_left, subtree_right) = children[index];
+
+        let mut writer = &mut buffer[offsets[index]..];
+        writer.write_u16::<LE>(subtree_left)?;
+        writer.write_u16::<LE>(subtree_right)?;
+        writer.write_u32::<LE>(new_entry.sector)?;
+        writer.write_u32::<LE>(new_entry.size)?;
+        writer.write_u8(new_entry.attributes.bits())?;
+        writer.write_u8(new_entry.name.len() as u8)?;
+        writer.write_all(new_entry.name.as_bytes())?;
+    }
+
+    Ok(buffer)
+}
+
+/// Returns `(subtree_left, subtree_right)` for every entry, for the same tree
+/// shape as [`pre_order`] builds. Offsets are in 4-byte units, 0 means none;
+/// they have to fit into 16 bits.
+fn subtree_offsets(offsets: &[usize]) -> Vec<(u16, u16)> {
+    let mut children = vec![(0, 0); offsets.len()];
+    rec(0, offsets.len(), offsets, &mut children);
+    return children;
+
+    fn rec(start: usize, end: usize, offsets: &[usize], children: &mut [(u16, u16)]) -> u16 {
+        if start >= end {
+            return 0;
+        }
+        let mid = start + (end - start) / 2;
+        let left = rec(start, mid, offsets, children);
+        let right = rec(mid + 1, end, offsets, children);
+        children[mid] = (left, right);
+        (offsets[mid] / 4) as u16
+    }
+}
+
+fn volume_descriptor_sector(
+    root_directory_sector: u32,
+    root_directory_size: u32,
+    image_creation_time: FileTime,
+) -> Result<Vec<u8>, Error> {
+    let mut buffer = vec![0_u8; SECTOR_SIZE as usize];
+
+    let mut writer = &mut buffer[..];
+    writer.write_all(b"MICROSOFT*XBOX*MEDIA")?;
+    writer.write_u32::<LE>(root_directory_sector)?;
+    writer.write_u32::<LE>(root_directory_size)?;
+    writer.write_u64::<LE>(image_creation_time.0)?;
+
+    buffer[0x07ec..].copy_from_slice(b"MICROSOFT*XBOX*MEDIA");
+
+    Ok(buffer)
+}
+
+pub struct RebuiltImageReader<'a, R: Read + Seek> {
+    image: &'a RebuiltImage,
+    source: R,
+    position: u64,
+}
+
+impl<R: Read + Seek> Read for RebuiltImageReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.image.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let extents = &self.image.layout.extents;
+        let index = extents.partition_point(|e| e.offset + e.len <= self.position);
+
+        let len = match extents.get(index) {
+            Some(extent) if extent.offset <= self.position => {
+                let extent_position = self.position - extent.offset;
+                let len = buf.len().min((extent.len - extent_position) as usize);
+
+                match &extent.data {
+                    ExtentData::Bytes(data) => {
+                        let start = extent_position as usize;
+                        buf[..len].copy_from_slice(&data[start..start + len]);
+                        len
+                    }
+                    ExtentData::Source(source_position) => {
+                        self.source
+                            .seek(SeekFrom::Start(source_position + extent_position))?;
+
+                        // returning 0 here would look like the end of the image
+                        match self.source.read(&mut buf[..len])? {
+                            0 => {
+                                return Err(std::io::Error::new(
+                                    std::io::ErrorKind::UnexpectedEof,
+                                    "source image ends inside of a file",
+                                ));
+                            }
+                            len => len,
+                        }
+                    }
+                }
+            }
+            next_extent => {
+                let gap_end = next_extent.map_or(self.image.size, |e| e.offset);
+                let len = buf.len().min((gap_end - self.position) as usize);
+                buf[..len].fill(0);
+                len
+            }
+        };
+
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for RebuiltImageReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.image.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::io::Cursor;
+
+    use tempfile::tempdir;
+
+    fn directory_image(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let dir = tempdir().unwrap();
+        for (name, data) in files {
+            fs::write(dir.path().join(name), data).unwrap();
+        }
+
+        let mut image = Vec::new();
+        DirectoryImage::new(dir.path())
+            .unwrap()
+            .write(&mut image)
+            .unwrap();
+        image
+    }
+
+    #[test]
+    fn too_large_directory_table_is_an_error() {
+        let mut entries: Vec<NewEntry> = (0..1200)
+            .map(|i| NewEntry {
+                name: format!("{i:0>240}"),
+                attributes: DirectoryEntryAttributes::ARCHIVE,
+                sector: 0,
+                size: 0,
+            })
+            .collect();
+
+        assert!(encode_directory_table(&mut entries).is_err());
+    }
+
+    #[test]
+    fn truncated_source_is_an_error() {
+        let image = directory_image(&[("default.xex", &[0x5a; 0x3000])]);
+        let iso = IsoReader::read(Cursor::new(&image)).unwrap();
+        let rebuilt = RebuiltImage::new(&iso).unwrap();
+
+        // cut the source off in the middle of the file
+        let entry = &iso.directory_table.entries[0];
+        let file_end = (entry.sector as usize) * (SECTOR_SIZE as usize) + 0x1000;
+        let source = Cursor::new(&image[..file_end]);
+
+        let mut data = Vec::new();
+        let error = rebuilt.reader(source).read_to_end(&mut data).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error};
+
+use super::iso_type::IsoType;
+
+/// Reads an image split into several files (e.g. because of the 4 GiB limit
+/// of FAT32) as one volume. A file that is not part of a split image is read
+/// as a volume of its own.
+pub struct SplitReader {
+    parts: Vec<SplitPart>,
+    size: u64,
+    position: u64,
+}
+
+struct SplitPart {
+    file: File,
+    offset: u64,
+    len: u64,
+}
+
+impl SplitReader {
+    /// Opens all chunks of the split image `path` is a part of, as found by
+    /// [`split_image_parts`], or just `path` itself.
+    pub fn open(path: &Path) -> Result<SplitReader, Error> {
+        let paths = split_image_parts(path).unwrap_or_else(|| vec![path.to_owned()]);
+
+        let files = paths
+            .iter()
+            .map(|path| {
+                File::open(path).with_context(|| format!("error opening {}", path.display()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_files(files)
+    }
+
+    /// Reads the files one after another.
+    pub fn from_files(files: Vec<File>) -> Result<SplitReader, Error> {
+        let mut parts = Vec::with_capacity(files.len());
+        let mut offset = 0;
+
+        for file in files {
+            let len = file.metadata()?.len();
+            parts.push(SplitPart { file, offset, len });
+            offset += len;
+        }
+
+        Ok(SplitReader {
+            parts,
+            size: offset,
+            position: 0,
+        })
+    }
+
+    /// Another reader over the same files, with its own position.
+    pub fn try_clone(&self) -> Result<SplitReader, Error> {
+        let parts = self
+            .parts
+            .iter()
+            .map(|part| {
+                Ok(SplitPart {
+                    file: part.file.try_clone()?,
+                    offset: part.offset,
+                    len: part.len,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(SplitReader {
+            parts,
+            size: self.size,
+            position: 0,
+        })
+    }
+
+    /// Total size of all chunks.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.parts.len()
+    }
+}
+
+impl Read for SplitReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let index = self
+            .parts
+            .partition_point(|p| p.offset + p.len <= self.position);
+
+        let Some(part) = self.parts.get_mut(index) else {
+            return Ok(0);
+        };
+
+        let part_position = self.position - part.offset;
+        let len = buf
+            .len()
+            .min((part.len - part_position).try_into().unwrap_or(usize::MAX));
+
+        part.file.seek(SeekFrom::Start(part_position))?;
+        let len = part.file.read(&mut buf[..len])?;
+
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for SplitReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+/// If `path` looks like a chunk of a split image, named either `name.iso.1`,
+/// `name.iso.2`, ... or `name.1.iso`, `name.2.iso`, ..., returns the paths of
+/// all its chunks in order. Numbering starts at 0 or 1, and ends with the
+/// first missing chunk; if that leaves out `path` itself, it is not treated as
+/// a chunk.
+///
+/// Since names like `game.1.iso` are also used for versions of whole images,
+/// the chunks also have to look like one: only the first one holds the Xbox
+/// volume descriptor, and all but the last one are of the same size.
+pub fn split_image_parts(path: &Path) -> Option<Vec<PathBuf>> {
+    let file_name = path.file_name()?.to_str()?;
+
+    let (stem, ext) = file_name.rsplit_once('.')?;
+
+    // chunk names are `{prefix}{n}{suffix}`
+    let (prefix, suffix) = if is_number(ext)
+        && stem
+            .rsplit_once('.')
+            .is_some_and(|(_, iso)| iso.eq_ignore_ascii_case("iso"))
+    {
+        (format!("{stem}."), String::new())
+    } else if ext.eq_ignore_ascii_case("iso")
+        && let Some((base, number)) = stem.rsplit_once('.')
+        && is_number(number)
+    {
+        (format!("{base}."), format!(".{ext}"))
+    } else {
+        return None;
+    };
+
+    let chunk_path = |n: u32| path.with_file_name(format!("{prefix}{n}{suffix}"));
+
+    let first = if chunk_path(0).is_file() { 0 } else { 1 };
+
+    let parts: Vec<PathBuf> = (first..)
+        .map(chunk_path)
+        .take_while(|path| path.is_file())
+        .collect();
+
+    // e.g. `name.iso.5` when chunk 4 is missing
+    if !parts.iter().any(|part| part == path) {
+        return None;
+    }
+
+    if !is_split_image(&parts).unwrap_or(false) {
+        return None;
+    }
+
+    Some(parts)
+}
+
+fn is_split_image(parts: &[PathBuf]) -> Result<bool, Error> {
+    let (last, init) = parts.split_last().context("no chunks")?;
+
+    let first_len = std::fs::metadata(&parts[0])?.len();
+    for part in init {
+        if std::fs::metadata(part)?.len() != first_len {
+            return Ok(false);
+        }
+    }
+    if std::fs::metadata(last)?.len() > first_len {
+        return Ok(false);
+    }
+
+    for (index, part) in parts.iter().enumerate() {
+        let has_volume_descriptor = IsoType::read(File::open(part)?)?.is_some();
+        if has_volume_descriptor != (index == 0) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::iso::SECTOR_SIZE;
+
+    const CHUNK_SIZE: usize = 0x20 * SECTOR_SIZE as usize + 0x800;
+
+    fn write_chunk(path: &Path, len: usize, volume_descriptor: bool) {
+        let mut chunk = vec![0; len];
+        if volume_descriptor {
+            let offset = 0x20 * SECTOR_SIZE as usize;
+            chunk[offset..offset + 20].copy_from_slice(b"MICROSOFT*XBOX*MEDIA");
+        }
+        fs::write(path, chunk).unwrap();
+    }
+
+    #[test]
+    fn chunks_are_found_in_order() {
+        let dir = tempdir().unwrap();
+        write_chunk(&dir.path().join("game.iso.1"), CHUNK_SIZE, true);
+        write_chunk(&dir.path().join("game.iso.2"), CHUNK_SIZE, false);
+        write_chunk(&dir.path().join("game.iso.3"), 0x800, false);
+
+        let parts = split_image_parts(&dir.path().join("game.iso.2")).unwrap();
+        let names: Vec<_> = parts.iter().map(|p| p.file_name().unwrap()).collect();
+        assert_eq!(names, ["game.iso.1", "game.iso.2", "game.iso.3"]);
+    }
+
+    #[test]
+    fn chunk_after_a_gap_is_read_alone() {
+        let dir = tempdir().unwrap();
+        write_chunk(&dir.path().join("game.1.iso"), CHUNK_SIZE, true);
+        for n in [2, 3, 5] {
+            write_chunk(&dir.path().join(format!("game.{n}.iso")), CHUNK_SIZE, false);
+        }
+
+        assert!(split_image_parts(&dir.path().join("game.5.iso")).is_none());
+    }
+
+    #[test]
+    fn versions_of_whole_images_are_read_alone() {
+        let dir = tempdir().unwrap();
+        write_chunk(&dir.path().join("game.1.iso"), CHUNK_SIZE, true);
+        write_chunk(&dir.path().join("game.2.iso"), CHUNK_SIZE, true);
+
+        assert!(split_image_parts(&dir.path().join("game.1.iso")).is_none());
+        assert!(split_image_parts(&dir.path().join("game.2.iso")).is_none());
+    }
+
+    #[test]
+    fn chunks_of_different_sizes_are_read_alone() {
+        let dir = tempdir().unwrap();
+        write_chunk(&dir.path().join("game.iso.1"), CHUNK_SIZE, true);
+        write_chunk(&dir.path().join("game.iso.2"), CHUNK_SIZE - 0x800, false);
+        write_chunk(&dir.path().join("game.iso.3"), CHUNK_SIZE, false);
+
+        assert!(split_image_parts(&dir.path().join("game.iso.1")).is_none());
+    }
+}
@@ -2,27 +2,29 @@ use byteorder::{LE, ReadBytesExt};
 
 use std::io::{Read, Seek, SeekFrom};
 
-use anyhow::{Error, format_err};
+use anyhow::{Error, bail};
+
+use crate::Iso2GodError;
 
 use super::iso_type::*;
 use super::*;
 
 #[derive(Debug)]
 pub struct VolumeDescriptor {
+    pub iso_type: IsoType,
     pub root_offset: u64,
     pub sector_size: u64,
     pub identifier: [u8; 20],
     pub root_directory_sector: u32,
     pub root_directory_size: u32,
-    pub image_creation_time: [u8; 8],
+    pub image_creation_time: FileTime,
     pub volume_size: u64,
     pub volume_sectors: u64,
 }
 
 impl VolumeDescriptor {
     pub fn read<R: Read + Seek>(mut reader: R) -> Result<VolumeDescriptor, Error> {
-        let iso_type =
-            IsoType::read(&mut reader)?.ok_or_else(|| format_err!("invalid ISO format"))?;
+        let iso_type = IsoType::read(&mut reader)?.ok_or(Iso2GodError::NotXboxImage)?;
         Self::read_of_type(reader, iso_type)
     }
 
@@ -38,9 +40,7 @@ impl VolumeDescriptor {
         let root_dir_sector = reader.read_u32::<LE>()?;
         let root_dir_size = reader.read_u32::<LE>()?;
 
-        // TODO: more specific type?
-        let mut image_creation_time = [0_u8; 8];
-        reader.read_exact(&mut image_creation_time)?;
+        let image_creation_time = FileTime(reader.read_u64::<LE>()?);
 
         let reader_len = {
             let cur = reader.stream_position()?;
@@ -49,10 +49,13 @@ impl VolumeDescriptor {
             end
         };
 
-        let volume_size = reader_len - iso_type.root_offset();
+        let Some(volume_size) = reader_len.checked_sub(iso_type.root_offset()) else {
+            bail!("image ends before its game partition");
+        };
         let volume_sectors = volume_size / SECTOR_SIZE;
 
         Ok(VolumeDescriptor {
+            iso_type,
             sector_size: SECTOR_SIZE,
             root_offset: iso_type.root_offset(),
             identifier,
@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error, bail};
+
+use super::*;
+
+/// An XSF-style (root offset 0) image built from a directory tree on disk,
+/// e.g. for homebrew or patched discs.
+///
+/// Files are read from disk only when the image is written, so they should
+/// not change in between.
+pub struct DirectoryImage {
+    layout: ImageLayout<PathBuf>,
+    pub size: u64,
+}
+
+impl DirectoryImage {
+    pub fn new(source_dir: &Path) -> Result<DirectoryImage, Error> {
+        let root = read_dir_entries(source_dir)?;
+        let layout = ImageLayout::new(root, FileTime::now())?;
+
+        Ok(DirectoryImage {
+            size: layout.size,
+            layout,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        let mut position = 0;
+
+        for extent in self.layout.extents.iter() {
+            write_zeroes(&mut writer, extent.offset - position)?;
+
+            match &extent.data {
+                ExtentData::Bytes(data) => writer.write_all(data)?,
+                ExtentData::Source(path) => {
+                    let file = File::open(path)
+                        .with_context(|| format!("error opening {}", path.display()))?;
+
+                    let len = io::copy(&mut file.take(extent.len), &mut writer)?;
+                    if len < extent.len {
+                        bail!("{} was truncated while writing the image", path.display());
+                    }
+                }
+            }
+
+            position = extent.offset + extent.len;
+        }
+
+        write_zeroes(&mut writer, self.size - position)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+fn write_zeroes<W: Write>(writer: &mut W, len: u64) -> Result<(), Error> {
+    io::copy(&mut io::repeat(0).take(len), writer)?;
+    Ok(())
+}
+
+fn read_dir_entries(dir_path: &Path) -> Result<Vec<LayoutEntry<PathBuf>>, Error> {
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+
+    let mut dir_entries = fs::read_dir(dir_path)
+        .with_context(|| format!("error reading folder {}", dir_path.display()))?
+        .collect::<Result<Vec<_>, _>>()?;
+    dir_entries.sort_by_key(|e| e.file_name());
+
+    for dir_entry in dir_entries {
+        let path = dir_entry.path();
+
+        let Some(name) = dir_entry.file_name().to_str().map(str::to_owned) else {
+            bail!("file name {} is not valid UTF-8", path.display());
+        };
+
+        if name.len() > u8::MAX as usize {
+            bail!("file name {} is too long for GDF", path.display());
+        }
+
+        // GDF lookups are case-insensitive
+        if !names.insert(name.to_ascii_uppercase()) {
+            bail!(
+                "file name {} differs from another one only in case",
+                path.display()
+            );
+        }
+
+        // symlinks are followed
+        let metadata = fs::metadata(&path)
+            .with_context(|| format!("error reading metadata of {}", path.display()))?;
+
+        let mut attributes = DirectoryEntryAttributes::empty();
+        if metadata.permissions().readonly() {
+            attributes |= DirectoryEntryAttributes::READ_ONLY;
+        }
+
+        let content = if metadata.is_dir() {
+            attributes |= DirectoryEntryAttributes::DIRECTORY;
+            LayoutContent::Directory(read_dir_entries(&path)?)
+        } else {
+            attributes |= DirectoryEntryAttributes::ARCHIVE;
+
+            let Ok(size) = u32::try_from(metadata.len()) else {
+                bail!("file {} is too large for GDF", path.display());
+            };
+
+            LayoutContent::File(size, path)
+        };
+
+        entries.push(LayoutEntry {
+            name,
+            attributes,
+            content,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn iso_reader_reads_back_written_image() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("media")).unwrap();
+        fs::write(dir.path().join("default.xex"), b"XEX2").unwrap();
+        fs::write(dir.path().join("media").join("empty.bin"), b"").unwrap();
+        for i in 0..40 {
+            let data = vec![i as u8; 0x700 * i];
+            fs::write(dir.path().join("media").join(format!("file{i}.bin")), data).unwrap();
+        }
+
+        let mut image = Vec::new();
+        let directory_image = DirectoryImage::new(dir.path()).unwrap();
+        directory_image.write(&mut image).unwrap();
+        assert_eq!(image.len() as u64, directory_image.size);
+
+        let iso = IsoReader::read(Cursor::new(image)).unwrap();
+        assert_eq!(iso.volume_descriptor.root_offset, 0);
+        assert_eq!(iso.directory_table.walk().count(), 43);
+
+        let read = |path: &str| {
+            let mut data = Vec::new();
+            let mut file = iso.get_entry(&path.into()).unwrap().unwrap();
+            file.read_to_end(&mut data).unwrap();
+            data
+        };
+
+        assert_eq!(read("DEFAULT.XEX"), b"XEX2");
+        assert_eq!(read("media\\empty.bin"), b"");
+        for i in 0..40 {
+            let data = read(&format!("media\\file{i}.bin"));
+            assert_eq!(data, vec![i as u8; 0x700 * i]);
+        }
+    }
+}
@@ -1,4 +1,7 @@
+mod error;
 pub mod executable;
 pub mod game_list;
 pub mod god;
 pub mod iso;
+
+pub use error::*;